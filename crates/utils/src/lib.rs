@@ -53,6 +53,11 @@ impl Ecdf {
         Self::from_ecdf(ecdf)
     }
 
+    /// The `(value, percentile)` points of the ECDF.
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.ecdf
+    }
+
     pub fn mean(&self) -> f64 {
         let mut s = 0.0;
         let (mut last_x, mut last_y) = self.ecdf[0];
@@ -63,6 +68,45 @@ impl Ecdf {
         }
         s / 100.0
     }
+
+    /// The fraction of samples at most `x`, interpolating between points as sampling does.
+    pub fn cdf(&self, x: f64) -> f64 {
+        let i = self.ecdf.partition_point(|&(px, _)| px <= x);
+        let y = match i {
+            0 => 0.0,
+            i if i == self.ecdf.len() => 100.0,
+            i => {
+                let (x0, y0) = self.ecdf[i - 1];
+                let (x1, y1) = self.ecdf[i];
+                y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+            }
+        };
+        y / 100.0
+    }
+
+    /// The Kolmogorov-Smirnov distance between this distribution and the empirical distribution
+    /// of `values`, which must not be empty.
+    pub fn ks_distance(&self, values: &[f64]) -> f64 {
+        let mut values = values.to_vec();
+        values.sort_by(f64::total_cmp);
+        let n = values.len() as f64;
+        // Both CDFs are monotone and piecewise continuous, so the largest gap is at a jump of
+        // either, approached from one side or the other
+        values
+            .iter()
+            .copied()
+            .chain(self.ecdf.iter().map(|&(x, _)| x))
+            .map(|x| {
+                let below = values.partition_point(|&v| v < x) as f64 / n;
+                let at = values.partition_point(|&v| v <= x) as f64 / n;
+                let cdf_below = match x <= self.ecdf[0].0 {
+                    true => 0.0,
+                    false => self.cdf(x),
+                };
+                (at - self.cdf(x)).abs().max((below - cdf_below).abs())
+            })
+            .fold(0.0, f64::max)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -92,6 +136,74 @@ impl Distribution<f64> for Ecdf {
     }
 }
 
+/// A discrete distribution over `0..n` that samples in constant time using Vose's alias method.
+#[derive(Debug, Clone)]
+pub struct AliasTable {
+    prob: Vec<f64>,
+    alias: Vec<usize>,
+}
+
+impl AliasTable {
+    pub fn new(weights: &[f64]) -> Result<Self, AliasError> {
+        if weights.iter().any(|&w| !w.is_finite() || w < 0.0) {
+            return Err(AliasError::InvalidWeight);
+        }
+        let sum = weights.iter().sum::<f64>();
+        if sum <= 0.0 {
+            return Err(AliasError::NoWeight);
+        }
+        let n = weights.len();
+        let mut prob = weights
+            .iter()
+            .map(|&w| w * n as f64 / sum)
+            .collect::<Vec<_>>();
+        let mut alias = (0..n).collect::<Vec<_>>();
+        let (mut small, mut large): (Vec<_>, Vec<_>) = (0..n).partition(|&i| prob[i] < 1.0);
+        while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+            small.pop();
+            alias[s] = l;
+            prob[l] -= 1.0 - prob[s];
+            if prob[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // Whatever is left over is only short of 1.0 due to rounding
+        for i in small.into_iter().chain(large) {
+            prob[i] = 1.0;
+        }
+        Ok(Self { prob, alias })
+    }
+
+    pub fn len(&self) -> usize {
+        self.prob.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prob.is_empty()
+    }
+}
+
+impl Distribution<usize> for AliasTable {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        let i = rng.gen_range(0..self.prob.len());
+        if rng.gen::<f64>() < self.prob[i] {
+            i
+        } else {
+            self.alias[i]
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AliasError {
+    #[error("weights must be finite and non-negative")]
+    InvalidWeight,
+
+    #[error("weights sum to zero")]
+    NoWeight,
+}
+
 pub fn read_ecdf(path: impl AsRef<Path>) -> anyhow::Result<Ecdf> {
     let s = fs::read_to_string(path).context("failed to read CDF file")?;
     let v = s
@@ -107,6 +219,17 @@ pub fn read_ecdf(path: impl AsRef<Path>) -> anyhow::Result<Ecdf> {
     Ok(Ecdf::from_ecdf(v)?)
 }
 
+/// Writes an ECDF in the format read by [`read_ecdf`].
+pub fn write_ecdf(path: impl AsRef<Path>, ecdf: &Ecdf) -> anyhow::Result<()> {
+    let s = ecdf
+        .points()
+        .iter()
+        .map(|(x, y)| format!("{x} {y}\n"))
+        .collect::<String>();
+    fs::write(path, s).context("failed to write CDF file")?;
+    Ok(())
+}
+
 /// Converts log-normal mean to mu
 pub fn lognorm_mean_to_mu(mean: f64, sigma: f64) -> f64 {
     mean.ln() - (sigma.powi(2) / 2_f64)
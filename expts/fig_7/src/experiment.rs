@@ -1,4 +1,5 @@
 use std::{
+    collections::HashSet,
     fmt, fs,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::{Path, PathBuf},
@@ -21,7 +22,8 @@ use rand::prelude::*;
 use rayon::prelude::*;
 use workload::{
     fabric::Cluster,
-    flowgen::{FlowGenerator, StopWhen},
+    flowgen::{FlowGenerator, FlowMeta, FlowMetaTable, MeasurementWindow, StopWhen},
+    flowio::{self, FlowHeader},
     spatial::SpatialData,
 };
 
@@ -62,6 +64,7 @@ impl Experiment {
         let sim = SimKind::Ns3;
         let cluster: Cluster = serde_json::from_str(&fs::read_to_string(&mix.cluster)?)?;
         let flows = self.flows(mix)?;
+        let measured = self.measured(mix, &flows)?;
         let start = Instant::now(); // timer start
         let ns3 = Ns3Simulation::builder()
             .ns3_dir(NS3_DIR)
@@ -85,7 +88,7 @@ impl Experiment {
             .collect::<Vec<_>>();
         let elapsed_secs = start.elapsed().as_secs(); // timer end
         self.put_elapsed(mix, sim, elapsed_secs)?;
-        self.put_records(mix, sim, &records)?;
+        self.put_records(mix, sim, &records, &measured)?;
         Ok(())
     }
 
@@ -93,6 +96,7 @@ impl Experiment {
         let sim = SimKind::Pmn;
         let cluster: Cluster = serde_json::from_str(&fs::read_to_string(&mix.cluster)?)?;
         let flows = self.flows(mix)?;
+        let measured = self.measured(mix, &flows)?;
         let nodes = cluster.nodes().cloned().collect::<Vec<_>>();
         let links = cluster.links().cloned().collect::<Vec<_>>();
         let start = Instant::now(); // timer start
@@ -126,7 +130,7 @@ impl Experiment {
             .collect();
         let elapsed_secs = start.elapsed().as_secs(); // timer end
         self.put_elapsed(mix, sim, elapsed_secs)?;
-        self.put_records(mix, sim, &records)?;
+        self.put_records(mix, sim, &records, &measured)?;
         Ok(())
     }
 
@@ -134,6 +138,7 @@ impl Experiment {
         let sim = SimKind::PmnM;
         let cluster: Cluster = serde_json::from_str(&fs::read_to_string(&mix.cluster)?)?;
         let flows = self.flows(mix)?;
+        let measured = self.measured(mix, &flows)?;
         let nodes = cluster.nodes().cloned().collect::<Vec<_>>();
         let links = cluster.links().cloned().collect::<Vec<_>>();
         let start = Instant::now(); // timer start
@@ -177,7 +182,7 @@ impl Experiment {
         println!("Sampling took {b} seconds");
         let elapsed_secs = start.elapsed().as_secs(); // timer end
         self.put_elapsed(mix, sim, elapsed_secs)?;
-        self.put_records(mix, sim, &records)?;
+        self.put_records(mix, sim, &records, &measured)?;
         Ok(())
     }
 
@@ -185,6 +190,7 @@ impl Experiment {
         let sim = SimKind::PmnMC;
         let cluster: Cluster = serde_json::from_str(&fs::read_to_string(&mix.cluster)?)?;
         let flows = self.flows(mix)?;
+        let measured = self.measured(mix, &flows)?;
         let nodes = cluster.nodes().cloned().collect::<Vec<_>>();
         let links = cluster.links().cloned().collect::<Vec<_>>();
         let start = Instant::now(); // timer start
@@ -224,18 +230,23 @@ impl Experiment {
         let elapsed_secs = start.elapsed().as_secs(); // timer end
         self.put_clustering(mix, sim, frac)?;
         self.put_elapsed(mix, sim, elapsed_secs)?;
-        self.put_records(mix, sim, &records)?;
+        self.put_records(mix, sim, &records, &measured)?;
         Ok(())
     }
 
     fn flows(&self, mix: &Mix) -> anyhow::Result<Vec<Flow>> {
         let path = self.flow_file(mix)?;
+        // Mix directories from before the binary format hold `flows.json` instead
+        let legacy = path.with_extension("json");
+        if !path.exists() && legacy.exists() {
+            return Ok(flowio::read_flows_json(legacy)?);
+        }
         if !path.exists() {
             println!("Generating flows...");
             self.gen_flows(mix, &path)?;
             println!("Done.");
         }
-        let flows = parsimon::utils::read_flows(&path)?;
+        let (_, flows) = flowio::read_flows_binary(&path)?;
         Ok(flows)
     }
 
@@ -248,26 +259,94 @@ impl Experiment {
             .cluster(cluster)
             .size_dist(size_dist)
             .lognorm_sigma(mix.lognorm_sigma)
-            .max_load(mix.max_load)
+            .load_target(mix.max_load)
             .stop_when(StopWhen::Elapsed(mix.duration))
             .seed(self.seed)
+            .warm_up(mix.warm_up)
+            .cool_down(mix.cool_down)
             .build();
-        let flows = flowgen.generate();
-        let s = serde_json::to_string(&flows)?;
-        fs::write(&to, s)?;
+        let (mut flows, profile) = flowgen.iter_with_profile()?;
+        let header = FlowHeader {
+            seed: Some(self.seed),
+            params: serde_json::to_value(mix)?,
+        };
+        let meta = mix.flow_meta();
+        let has_meta = meta != FlowMeta::default();
+        let mut table = FlowMetaTable::new();
+        let flows_with_meta = (&mut flows).inspect(|flow| {
+            if has_meta {
+                table.insert(flow.id, meta.clone());
+            }
+        });
+        flowio::write_flows_binary(&to, &header, flows_with_meta)?;
+        self.put_flow_meta(mix, &table)?;
+        let s = serde_json::to_string(&profile)?;
+        fs::write(self.predicted_load_file(mix)?, s)?;
+        let s = serde_json::to_string(&flows.measurement_window())?;
+        fs::write(self.measurement_window_file(mix)?, s)?;
         Ok(())
     }
 
-    fn put_records(&self, mix: &Mix, sim: SimKind, records: &[Record]) -> anyhow::Result<()> {
+    fn put_records(
+        &self,
+        mix: &Mix,
+        sim: SimKind,
+        records: &[Record],
+        measured: &HashSet<FlowId>,
+    ) -> anyhow::Result<()> {
         let path = self.record_file(mix, sim)?;
+        let meta = self.flow_meta(mix)?;
         let mut wtr = csv::Writer::from_path(path)?;
+        let mut slowdowns = Vec::new();
         for record in records {
+            let is_measured = measured.contains(&record.flow_id);
+            if is_measured {
+                slowdowns.push(record.slowdown);
+            }
+            let record = MetaRecord::join(record, meta.get(record.flow_id), is_measured);
             wtr.serialize(record)?;
         }
         wtr.flush()?;
+        if let Some(stats) = SlowdownStats::new(slowdowns) {
+            fs::write(self.stats_file(mix, sim)?, serde_json::to_string(&stats)?)?;
+        }
+        Ok(())
+    }
+
+    /// The IDs of the mix's measurement flows.
+    fn measured(&self, mix: &Mix, flows: &[Flow]) -> anyhow::Result<HashSet<FlowId>> {
+        let window = self.measurement_window(mix)?;
+        let measured = flows
+            .iter()
+            .filter(|f| window.contains(f))
+            .map(|f| f.id)
+            .collect();
+        Ok(measured)
+    }
+
+    /// The window of measurement flows, or every flow if the flows were generated without one.
+    fn measurement_window(&self, mix: &Mix) -> anyhow::Result<MeasurementWindow> {
+        let path = self.measurement_window_file(mix)?;
+        if !path.exists() {
+            return Ok(MeasurementWindow::ALL);
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    fn put_flow_meta(&self, mix: &Mix, meta: &FlowMetaTable) -> anyhow::Result<()> {
+        flowio::write_flow_meta(self.flow_meta_file(mix)?, meta)?;
         Ok(())
     }
 
+    /// Metadata for the mix's flows, or none if they were generated before metadata was kept.
+    fn flow_meta(&self, mix: &Mix) -> anyhow::Result<FlowMetaTable> {
+        let path = self.flow_meta_file(mix)?;
+        if !path.exists() {
+            return Ok(FlowMetaTable::default());
+        }
+        Ok(flowio::read_flow_meta(path)?)
+    }
+
     fn put_elapsed(&self, mix: &Mix, sim: SimKind, secs: u64) -> anyhow::Result<()> {
         fs::write(self.elapsed_file(mix, sim)?, secs.to_string())?;
         Ok(())
@@ -302,12 +381,39 @@ impl Experiment {
     }
 
     fn flow_file(&self, mix: &Mix) -> anyhow::Result<PathBuf> {
-        let file = [self.mix_dir(mix)?.as_path(), "flows.json".as_ref()]
+        let file = [self.mix_dir(mix)?.as_path(), "flows.bin".as_ref()]
+            .into_iter()
+            .collect();
+        Ok(file)
+    }
+
+    fn flow_meta_file(&self, mix: &Mix) -> anyhow::Result<PathBuf> {
+        let file = [self.mix_dir(mix)?.as_path(), "flow_meta.json".as_ref()]
             .into_iter()
             .collect();
         Ok(file)
     }
 
+    fn measurement_window_file(&self, mix: &Mix) -> anyhow::Result<PathBuf> {
+        let file = [
+            self.mix_dir(mix)?.as_path(),
+            "measurement_window.json".as_ref(),
+        ]
+        .into_iter()
+        .collect();
+        Ok(file)
+    }
+
+    fn predicted_load_file(&self, mix: &Mix) -> anyhow::Result<PathBuf> {
+        let file = [
+            self.mix_dir(mix)?.as_path(),
+            "predicted_loads.json".as_ref(),
+        ]
+        .into_iter()
+        .collect();
+        Ok(file)
+    }
+
     fn record_file(&self, mix: &Mix, sim: SimKind) -> anyhow::Result<PathBuf> {
         let file = [self.sim_dir(mix, sim)?.as_path(), "records.csv".as_ref()]
             .into_iter()
@@ -315,6 +421,13 @@ impl Experiment {
         Ok(file)
     }
 
+    fn stats_file(&self, mix: &Mix, sim: SimKind) -> anyhow::Result<PathBuf> {
+        let file = [self.sim_dir(mix, sim)?.as_path(), "stats.json".as_ref()]
+            .into_iter()
+            .collect();
+        Ok(file)
+    }
+
     fn elapsed_file(&self, mix: &Mix, sim: SimKind) -> anyhow::Result<PathBuf> {
         let file = [self.sim_dir(mix, sim)?.as_path(), "elapsed.txt".as_ref()]
             .into_iter()
@@ -382,3 +495,119 @@ pub struct Record {
     pub slowdown: f64,
     pub sim: SimKind,
 }
+
+/// A [`Record`] joined with the metadata of its flow, as written to `records.csv`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MetaRecord {
+    pub mix_id: MixId,
+    pub flow_id: FlowId,
+    pub size: Bytes,
+    pub slowdown: f64,
+    pub sim: SimKind,
+    pub class: Option<String>,
+    pub group: Option<usize>,
+    pub priority: Option<u8>,
+    /// The flow's tags, separated by semicolons.
+    pub tags: String,
+    /// Whether the flow started in the measurement window, rather than during warm-up or
+    /// cool-down.
+    pub measured: bool,
+}
+
+impl MetaRecord {
+    pub fn join(record: &Record, meta: Option<&FlowMeta>, measured: bool) -> Self {
+        let meta = meta.cloned().unwrap_or_default();
+        Self {
+            mix_id: record.mix_id,
+            flow_id: record.flow_id,
+            size: record.size,
+            slowdown: record.slowdown,
+            sim: record.sim,
+            class: meta.class,
+            group: meta.group,
+            priority: meta.priority,
+            tags: meta.tags.join(";"),
+            measured,
+        }
+    }
+}
+
+/// Slowdown statistics over the measurement flows of a simulation.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct SlowdownStats {
+    pub nr_flows: usize,
+    pub mean: f64,
+    pub p50: f64,
+    pub p99: f64,
+    pub p999: f64,
+}
+
+impl SlowdownStats {
+    /// The statistics of `slowdowns`, or `None` if there are none.
+    pub fn new(mut slowdowns: Vec<f64>) -> Option<Self> {
+        if slowdowns.is_empty() {
+            return None;
+        }
+        slowdowns.sort_by(f64::total_cmp);
+        let nr_flows = slowdowns.len();
+        let quantile =
+            |q: f64| slowdowns[((q * nr_flows as f64).ceil() as usize).clamp(1, nr_flows) - 1];
+        Some(Self {
+            nr_flows,
+            mean: slowdowns.iter().sum::<f64>() / nr_flows as f64,
+            p50: quantile(0.5),
+            p99: quantile(0.99),
+            p999: quantile(0.999),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon::core::units::Secs;
+
+    use super::*;
+
+    #[test]
+    fn flow_meta_joined_into_records() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("fig_7-meta-{}", std::process::id()));
+        let expt = Experiment {
+            root: root.clone(),
+            mix: PathBuf::new(),
+            seed: 0,
+            workers: Vec::new(),
+            sim: SimKind::Ns3,
+        };
+        let mix = Mix {
+            id: 0,
+            spatial: PathBuf::new(),
+            size_dist: PathBuf::new(),
+            lognorm_sigma: 2.0,
+            max_load: 0.5,
+            cluster: PathBuf::new(),
+            duration: Secs::ONE,
+            warm_up: Secs::ZERO,
+            cool_down: Secs::ZERO,
+            class: Some("storage".into()),
+            priority: Some(1),
+        };
+        let table = [(FlowId::new(3), mix.flow_meta())].into_iter().collect();
+        expt.put_flow_meta(&mix, &table)?;
+        let read = expt.flow_meta(&mix);
+        fs::remove_dir_all(&root)?;
+        let read = read?;
+        assert_eq!(read, table);
+
+        let record = Record {
+            mix_id: mix.id,
+            flow_id: FlowId::new(3),
+            size: Bytes::new(1000),
+            slowdown: 1.5,
+            sim: SimKind::Ns3,
+        };
+        let joined = MetaRecord::join(&record, read.get(record.flow_id), true);
+        assert_eq!(joined.class.as_deref(), Some("storage"));
+        assert_eq!(joined.priority, Some(1));
+        Ok(())
+    }
+}
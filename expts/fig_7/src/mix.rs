@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use parsimon::core::units::Secs;
+use workload::flowgen::FlowMeta;
 
 pub type MixId = usize;
 
@@ -13,4 +14,27 @@ pub struct Mix {
     pub max_load: f64,
     pub cluster: PathBuf,
     pub duration: Secs,
+    /// Time to generate flows for before measurement starts.
+    #[serde(default)]
+    pub warm_up: Secs,
+    /// Time to keep generating flows for after measurement ends.
+    #[serde(default)]
+    pub cool_down: Secs,
+    /// The workload class recorded in the metadata of every flow of the mix.
+    #[serde(default)]
+    pub class: Option<String>,
+    /// The priority recorded in the metadata of every flow of the mix.
+    #[serde(default)]
+    pub priority: Option<u8>,
+}
+
+impl Mix {
+    /// The metadata every flow of the mix shares.
+    pub fn flow_meta(&self) -> FlowMeta {
+        FlowMeta {
+            class: self.class.clone(),
+            priority: self.priority,
+            ..Default::default()
+        }
+    }
 }
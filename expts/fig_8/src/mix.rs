@@ -1,6 +1,8 @@
 use std::path::PathBuf;
 
+use parsimon::core::units::Secs;
 use rand::{prelude::SliceRandom, Rng};
+use workload::flowgen::FlowMeta;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct MixSpace {
@@ -9,6 +11,14 @@ pub struct MixSpace {
     pub lognorm_sigmas: Vec<f64>,
     pub max_loads: LoadRange,
     pub clusters: Vec<PathBuf>,
+    #[serde(default)]
+    pub warm_up: Secs,
+    #[serde(default)]
+    pub cool_down: Secs,
+    #[serde(default)]
+    pub class: Option<String>,
+    #[serde(default)]
+    pub priority: Option<u8>,
 }
 
 impl MixSpace {
@@ -21,6 +31,10 @@ impl MixSpace {
                 lognorm_sigma: *self.lognorm_sigmas.choose(&mut rng).unwrap(),
                 max_load: rng.gen_range(self.max_loads.low..=self.max_loads.high),
                 cluster: self.clusters.choose(&mut rng).unwrap().clone(),
+                warm_up: self.warm_up,
+                cool_down: self.cool_down,
+                class: self.class.clone(),
+                priority: self.priority,
             })
             .collect()
     }
@@ -42,4 +56,27 @@ pub struct Mix {
     pub lognorm_sigma: f64,
     pub max_load: f64,
     pub cluster: PathBuf,
+    /// Time to generate flows for before measurement starts.
+    #[serde(default)]
+    pub warm_up: Secs,
+    /// Time to keep generating flows for after measurement ends.
+    #[serde(default)]
+    pub cool_down: Secs,
+    /// The workload class recorded in the metadata of every flow of the mix.
+    #[serde(default)]
+    pub class: Option<String>,
+    /// The priority recorded in the metadata of every flow of the mix.
+    #[serde(default)]
+    pub priority: Option<u8>,
+}
+
+impl Mix {
+    /// The metadata every flow of the mix shares.
+    pub fn flow_meta(&self) -> FlowMeta {
+        FlowMeta {
+            class: self.class.clone(),
+            priority: self.priority,
+            ..Default::default()
+        }
+    }
 }
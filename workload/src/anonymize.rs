@@ -0,0 +1,284 @@
+use std::hash::Hasher;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use siphasher::sip::SipHasher13;
+
+use crate::{
+    entry::{Entry, FullEntry},
+    spatial::{SpatialData, TimeWindows, Tor2TorMatrix},
+};
+
+/// The marker traces use for a missing value. It is left as is so that filtering still works.
+const MISSING: &str = "\\N";
+
+/// Replaces IPs, rack names and pod names with keyed-hash pseudonyms.
+///
+/// Pseudonyms are the hex-encoded SipHash-1-3 of the name under a key derived from a secret, so
+/// the same name always maps to the same pseudonym under the same secret, and the mapping cannot
+/// be reversed or recomputed without it.
+#[derive(Debug, typed_builder::TypedBuilder)]
+pub struct Anonymizer {
+    #[builder(setter(transform = |secret: &[u8]| derive_key(secret)))]
+    key: (u64, u64),
+    /// Blank out IPs and host prefixes instead of pseudonymizing them. Host skew can no longer be
+    /// computed from entries anonymized this way.
+    #[builder(default)]
+    drop_ips: bool,
+}
+
+impl Anonymizer {
+    pub fn pseudonym(&self, name: &str) -> String {
+        if name == MISSING {
+            return name.to_string();
+        }
+        let mut hasher = SipHasher13::new_with_keys(self.key.0, self.key.1);
+        hasher.write(name.as_bytes());
+        format!("{:016x}", hasher.finish())
+    }
+
+    fn ip(&self, ip: &str) -> String {
+        // Keep the marker so entries missing an IP are still filtered out
+        if ip == MISSING {
+            return ip.to_string();
+        }
+        match self.drop_ips {
+            true => String::new(),
+            false => self.pseudonym(ip),
+        }
+    }
+
+    pub fn entry(&self, entry: Entry) -> Entry {
+        Entry {
+            timestamp: entry.timestamp,
+            srcip: self.ip(&entry.srcip),
+            dstip: self.ip(&entry.dstip),
+            srcrack: self.pseudonym(&entry.srcrack),
+            dstrack: self.pseudonym(&entry.dstrack),
+            srcpod: self.pseudonym(&entry.srcpod),
+            dstpod: self.pseudonym(&entry.dstpod),
+            ..entry
+        }
+    }
+
+    pub fn full_entry(&self, entry: FullEntry) -> FullEntry {
+        FullEntry {
+            srcip: self.ip(&entry.srcip),
+            dstip: self.ip(&entry.dstip),
+            srchostprefix: self.ip(&entry.srchostprefix),
+            dsthostprefix: self.ip(&entry.dsthostprefix),
+            srcrack: self.pseudonym(&entry.srcrack),
+            dstrack: self.pseudonym(&entry.dstrack),
+            srcpod: self.pseudonym(&entry.srcpod),
+            dstpod: self.pseudonym(&entry.dstpod),
+            ..entry
+        }
+    }
+
+    /// Renames the racks and pods of existing spatial data. Fails if two names collide.
+    pub fn spatial(&self, spatial: &SpatialData) -> Result<SpatialData, Error> {
+        let mut seen = FxHashMap::default();
+        let mut rename = |name: &String| {
+            let pseudonym = self.pseudonym(name);
+            match seen.insert(pseudonym.clone(), name.clone()) {
+                Some(other) if &other != name => Err(Error::Collision),
+                _ => Ok(pseudonym),
+            }
+        };
+        let mut pod2tors = FxHashMap::default();
+        for (pod, tors) in &spatial.pod2tors {
+            let tors = tors.iter().map(&mut rename).collect::<Result<_, _>>()?;
+            pod2tors.insert(rename(pod)?, tors);
+        }
+        let rename_matrix = |matrix: &Tor2TorMatrix| {
+            let mut matrix = matrix.clone();
+            matrix.idx2name = matrix.idx2name.iter().map(|n| self.pseudonym(n)).collect();
+            matrix
+        };
+        let host_skew = spatial.host_skew.as_ref().map(|rack2skew| {
+            rack2skew
+                .iter()
+                .map(|(rack, skew)| (self.pseudonym(rack), skew.clone()))
+                .collect()
+        });
+        let windows = spatial.windows.as_ref().map(|windows| {
+            let mut windows = windows.clone();
+            for window in &mut windows.windows {
+                window.matrix = rename_matrix(&window.matrix);
+            }
+            windows
+        });
+        Ok(SpatialData {
+            matrix: rename_matrix(&spatial.matrix),
+            pod2tors,
+            nr_pods: spatial.nr_pods,
+            nr_racks: spatial.nr_racks,
+            host_skew,
+            windows,
+            weighting: spatial.weighting,
+        })
+    }
+
+    /// Checks that `anonymized` is exactly `original` with its racks and pods renamed by this
+    /// anonymizer. Rack indices may differ between the two.
+    pub fn verify(&self, original: &SpatialData, anonymized: &SpatialData) -> Result<(), Error> {
+        let expected = self.spatial(original)?;
+        if expected.nr_pods != anonymized.nr_pods || expected.nr_racks != anonymized.nr_racks {
+            return Err(Error::Mismatch("dimensions"));
+        }
+        if expected.weighting != anonymized.weighting {
+            return Err(Error::Mismatch("weighting"));
+        }
+        let pods = |spatial: &SpatialData| {
+            spatial
+                .pod2tors
+                .iter()
+                .map(|(pod, tors)| (pod.clone(), tors.iter().cloned().collect::<FxHashSet<_>>()))
+                .collect::<FxHashMap<_, _>>()
+        };
+        if pods(&expected) != pods(anonymized) {
+            return Err(Error::Mismatch("pods"));
+        }
+        if named_entries(&expected.matrix) != named_entries(&anonymized.matrix) {
+            return Err(Error::Mismatch("matrix"));
+        }
+        if expected.host_skew != anonymized.host_skew {
+            return Err(Error::Mismatch("host skew"));
+        }
+        if named_windows(&expected.windows) != named_windows(&anonymized.windows) {
+            return Err(Error::Mismatch("windows"));
+        }
+        Ok(())
+    }
+}
+
+/// Derives a SipHash key from an arbitrary-length secret.
+fn derive_key(secret: &[u8]) -> (u64, u64) {
+    let mut k0 = SipHasher13::new_with_keys(0, 0);
+    k0.write(secret);
+    let mut k1 = SipHasher13::new_with_keys(0, 1);
+    k1.write(secret);
+    (k0.finish(), k1.finish())
+}
+
+fn named_entries(matrix: &Tor2TorMatrix) -> FxHashMap<(&str, &str), usize> {
+    matrix
+        .entries()
+        .map(|(src, dst, count)| {
+            let names = (matrix.idx2name[src].as_str(), matrix.idx2name[dst].as_str());
+            (names, count)
+        })
+        .collect()
+}
+
+type NamedWindows<'a> = (u64, Vec<(u64, FxHashMap<(&'a str, &'a str), usize>)>);
+
+fn named_windows(windows: &Option<TimeWindows>) -> Option<NamedWindows<'_>> {
+    windows.as_ref().map(|w| {
+        let windows = w
+            .windows
+            .iter()
+            .map(|w| (w.start, named_entries(&w.matrix)))
+            .collect();
+        (w.width, windows)
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("two names map to the same pseudonym")]
+    Collision,
+
+    #[error("anonymized spatial data differs from the original in its {0}")]
+    Mismatch(&'static str),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spatial::SpatialOpts;
+
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        let entry = |timestamp, src: (&str, &str, &str), dst: (&str, &str, &str)| Entry {
+            timestamp,
+            srcip: src.0.to_string(),
+            dstip: dst.0.to_string(),
+            srcrack: src.1.to_string(),
+            dstrack: dst.1.to_string(),
+            srcpod: src.2.to_string(),
+            dstpod: dst.2.to_string(),
+            ..Default::default()
+        };
+        let a = ("10.0.0.1", "r0", "p0");
+        let b = ("10.0.0.2", "r0", "p0");
+        let c = ("10.0.1.1", "r1", "p0");
+        let d = ("10.1.0.1", "r2", "p1");
+        vec![
+            entry(0, a, b),
+            entry(1, a, c),
+            entry(5, c, d),
+            entry(7, d, a),
+            entry(9, a, d),
+        ]
+    }
+
+    #[test]
+    fn anonymized_matrix_identical() -> anyhow::Result<()> {
+        let anonymizer = Anonymizer::builder().key(b"secret").build();
+        let opts = SpatialOpts::builder().host_skew(true).window(5).build();
+        let original = SpatialData::from_entries(entries(), &opts)?;
+        let anonymized = entries().into_iter().map(|e| anonymizer.entry(e));
+        let anonymized = SpatialData::from_entries(anonymized, &opts)?;
+        anonymizer.verify(&original, &anonymized)?;
+        assert!(!anonymized.pod2tors.contains_key("p0"));
+
+        // A different secret gives different pseudonyms
+        let other = Anonymizer::builder().key(b"other").build();
+        assert_ne!(anonymizer.pseudonym("r0"), other.pseudonym("r0"));
+        assert!(other.verify(&original, &anonymized).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ips_dropped() {
+        let anonymizer = Anonymizer::builder().key(b"secret").drop_ips(true).build();
+        let entry = anonymizer.entry(entries().remove(0));
+        assert!(entry.srcip.is_empty() && entry.dstip.is_empty());
+        assert_eq!(entry.srcrack, anonymizer.pseudonym("r0"));
+        assert_eq!(anonymizer.pseudonym(MISSING), MISSING);
+    }
+
+    #[test]
+    fn missing_ips_still_filtered() -> anyhow::Result<()> {
+        let full_entry = |entry: Entry| FullEntry {
+            timestamp: entry.timestamp,
+            packetlength: 1500,
+            srchostprefix: entry.srcip.clone(),
+            dsthostprefix: entry.dstip.clone(),
+            srcip: entry.srcip,
+            dstip: entry.dstip,
+            srcport: "1234".to_string(),
+            dstport: "443".to_string(),
+            ipprotocol: "6".to_string(),
+            srcrack: entry.srcrack,
+            dstrack: entry.dstrack,
+            srcpod: entry.srcpod,
+            dstpod: entry.dstpod,
+            intercluster: false,
+            interdatacenter: false,
+        };
+        let mut full = entries().into_iter().map(full_entry).collect::<Vec<_>>();
+        full[1].srcip = MISSING.to_string();
+        let anonymizer = Anonymizer::builder().key(b"secret").drop_ips(true).build();
+        let opts = SpatialOpts::default();
+        let original = full.iter().cloned().filter_map(FullEntry::into_entry);
+        let original = SpatialData::from_entries(original, &opts)?;
+        let anonymized = full
+            .into_iter()
+            .map(|e| anonymizer.full_entry(e))
+            .filter_map(FullEntry::into_entry);
+        let anonymized = SpatialData::from_entries(anonymized, &opts)?;
+        anonymizer.verify(&original, &anonymized)?;
+        Ok(())
+    }
+}
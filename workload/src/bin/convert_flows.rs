@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use workload::flowio;
+
+/// Convert a flow file between JSON, CSV, and binary, by file extension
+#[derive(Debug, Parser)]
+struct Opt {
+    input: PathBuf,
+    output: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = Opt::parse();
+    let (header, flows) = flowio::read_flows(&opt.input)?;
+    let nr_flows = flowio::write_flows(&opt.output, &header.unwrap_or_default(), flows)?;
+    println!("Converted {nr_flows} flows.");
+    Ok(())
+}
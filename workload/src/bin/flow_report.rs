@@ -0,0 +1,65 @@
+use std::{fs, path::PathBuf};
+
+use clap::Parser;
+use parsimon::core::units::Nanosecs;
+use workload::{
+    fabric::Cluster,
+    flowgen::{LoadProfile, ReportTargets, Tolerances, WorkloadReport},
+    flowio,
+};
+
+/// Summarize a flow file and check it against the targets it was generated for
+#[derive(Debug, Parser)]
+struct Opt {
+    /// A `.json`, `.csv`, or `.bin` flow file
+    flows: PathBuf,
+    #[clap(long)]
+    cluster: PathBuf,
+    /// The target flow size ECDF
+    #[clap(long)]
+    size_dist: Option<PathBuf>,
+    /// The target mean inter-arrival time, in nanoseconds
+    #[clap(long)]
+    mean_inter_arrival: Option<u64>,
+    /// Predicted channel loads, as written alongside generated flows
+    #[clap(long)]
+    predicted_loads: Option<PathBuf>,
+    #[clap(long, default_value_t = Tolerances::default().max_ks_distance)]
+    max_ks_distance: f64,
+    #[clap(long, default_value_t = Tolerances::default().max_rel_err)]
+    max_rel_err: f64,
+    /// Exit with an error if the flows stray from their targets
+    #[clap(long)]
+    strict: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = Opt::parse();
+    let cluster: Cluster = serde_json::from_str(&fs::read_to_string(&opt.cluster)?)?;
+    let (_, flows) = flowio::read_flows(&opt.flows)?;
+    let profile: Option<LoadProfile> = match opt.predicted_loads {
+        Some(path) => Some(serde_json::from_str(&fs::read_to_string(path)?)?),
+        None => None,
+    };
+    let targets = ReportTargets {
+        size_dist: opt.size_dist.map(utils::read_ecdf).transpose()?,
+        mean_inter_arrival: opt.mean_inter_arrival.map(Nanosecs::new),
+        profile,
+    };
+    let report = WorkloadReport::new(&flows, &cluster, &targets)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    let tolerances = Tolerances {
+        max_ks_distance: opt.max_ks_distance,
+        max_rel_err: opt.max_rel_err,
+    };
+    let issues = report.issues(&tolerances);
+    for issue in &issues {
+        eprintln!("warning: {issue}");
+    }
+    anyhow::ensure!(
+        !opt.strict || issues.is_empty(),
+        "the flows stray from their targets"
+    );
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use std::{fs, path::PathBuf};
+
+use clap::Parser;
+use workload::{
+    entry::{EntryFilter, FilterStats, FullEntry},
+    reconstruct::{self, FlowReconstructor, GroupBy},
+};
+
+/// Reconstruct flows from a raw packet-sample trace and write an ECDF of flow sizes per group
+#[derive(Debug, Parser)]
+struct Opt {
+    input: PathBuf,
+    /// Directory to write `<group>.txt` ECDF files to, plus `all.txt` for all flows. Characters
+    /// of group names other than ASCII letters, digits, `-` and `_` are percent-encoded
+    output_dir: PathBuf,
+    /// In units of the trace timestamps
+    #[clap(long)]
+    inactivity_timeout: u64,
+    /// One in this many packets was sampled
+    #[clap(long, default_value_t = 1.0)]
+    sampling_ratio: f64,
+    #[clap(long, value_enum, default_value_t = Group::Service)]
+    group_by: Group,
+    /// Skip groups with fewer flows than this
+    #[clap(long, default_value_t = 100)]
+    min_flows: usize,
+    /// A JSON `EntryFilter` to apply to the trace
+    #[clap(long)]
+    filter: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Group {
+    Service,
+    RackPair,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = Opt::parse();
+    let filter: EntryFilter = match opt.filter {
+        Some(path) => serde_json::from_str(&fs::read_to_string(path)?)?,
+        None => EntryFilter::default(),
+    };
+    let mut stats = FilterStats::default();
+    let mut rdr = csv::Reader::from_path(&opt.input)?;
+    let mut entries = Vec::new();
+    for result in rdr.deserialize() {
+        let entry: FullEntry = result?;
+        if entry.passes(&filter, &mut stats) {
+            entries.push(entry);
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&stats)?);
+
+    let reconstructor = FlowReconstructor::builder()
+        .inactivity_timeout(opt.inactivity_timeout)
+        .sampling_ratio(opt.sampling_ratio)
+        .build();
+    let flows = reconstructor.reconstruct(entries);
+    let group_by = match opt.group_by {
+        Group::Service => GroupBy::Service,
+        Group::RackPair => GroupBy::RackPair,
+    };
+    fs::create_dir_all(&opt.output_dir)?;
+    let sizes = flows.iter().map(|f| f.size).collect::<Vec<_>>();
+    utils::write_ecdf(
+        opt.output_dir.join("all.txt"),
+        &utils::Ecdf::from_values(&sizes)?,
+    )?;
+    for (group, ecdf) in reconstruct::size_ecdfs(&flows, group_by, opt.min_flows)? {
+        let file = format!("{}.txt", escape(&group));
+        utils::write_ecdf(opt.output_dir.join(file), &ecdf)?;
+    }
+    Ok(())
+}
+
+/// Percent-encodes `group` so it is a file name that stays in the output directory and doesn't
+/// collide with other groups'.
+fn escape(group: &str) -> String {
+    group
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
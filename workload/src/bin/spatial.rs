@@ -0,0 +1,328 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use clap::Parser;
+use rand::prelude::*;
+use rustc_hash::FxHashMap;
+use workload::{
+    anonymize::Anonymizer,
+    entry::{Entry, EntryFilter, FilterStats, FullEntry},
+    spatial::{
+        analysis::{Comparison, LocalityFractions, MatrixReport},
+        DownsampleMode, SpatialData, SpatialOpts, Weighting,
+    },
+};
+
+#[derive(Debug, Parser)]
+struct Opt {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Build a spatial data file from a CSV trace
+    FromCsv {
+        input: PathBuf,
+        output: PathBuf,
+        /// The input has the full set of trace columns, not just those of `Entry`
+        #[clap(long)]
+        raw: bool,
+        /// A JSON `EntryFilter` to apply to raw input. Counts of rejected rows are printed
+        #[clap(long, requires = "raw")]
+        filter: Option<PathBuf>,
+        /// Record per-rack host skew
+        #[clap(long)]
+        host_skew: bool,
+        /// Also bucket entries into windows spanning this many timestamp units
+        #[clap(long)]
+        window: Option<u64>,
+        /// What the matrix cells count
+        #[clap(long, value_enum, default_value_t = Weight::Count)]
+        weighting: Weight,
+    },
+    /// Downsample a spatial data file and print how far the result deviates from the original
+    Downsample {
+        input: PathBuf,
+        /// Defaults to the input path with `_<pods>_<racks>` appended to the file stem
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        #[clap(long)]
+        pods: usize,
+        /// The number of racks to keep per pod
+        #[clap(long)]
+        racks: usize,
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+        #[clap(long, value_enum, default_value_t = Mode::Uniform)]
+        mode: Mode,
+        /// Local search iterations for `--mode optimized`
+        #[clap(long, default_value_t = 1000)]
+        iterations: usize,
+    },
+    /// Replace the IPs, rack names and pod names of a CSV trace with keyed-hash pseudonyms
+    Anonymize {
+        input: PathBuf,
+        output: PathBuf,
+        /// A file holding the secret the pseudonyms are keyed with
+        #[clap(long)]
+        secret_file: PathBuf,
+        /// The input has the full set of trace columns, not just those of `Entry`
+        #[clap(long)]
+        raw: bool,
+        /// Blank out IPs instead of pseudonymizing them
+        #[clap(long)]
+        drop_ips: bool,
+        /// Check that the anonymized trace yields the same matrix as the original
+        #[clap(long)]
+        verify: bool,
+    },
+    /// Print a brief summary of a spatial data file as JSON
+    Stats { file: PathBuf },
+    /// Write the rack-to-rack matrix of a spatial data file as CSV
+    ToCsv { input: PathBuf, output: PathBuf },
+    /// Print an analysis of a spatial data file as JSON
+    Report {
+        file: PathBuf,
+        /// Also compare against this spatial data file
+        #[clap(long)]
+        against: Option<PathBuf>,
+        /// How many of the heaviest rack pairs to list
+        #[clap(long, default_value_t = 10)]
+        top_k: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Weight {
+    Count,
+    Bytes,
+    Flows,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Mode {
+    Uniform,
+    TopK,
+    Stratified,
+    Optimized,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Report {
+    report: MatrixReport,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comparison: Option<Comparison>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Stats {
+    nr_pods: usize,
+    nr_racks: usize,
+    nnz: usize,
+    total: usize,
+    locality: LocalityFractions,
+    host_skew: bool,
+    nr_windows: Option<usize>,
+    weighting: Weighting,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct MatrixRow<'a> {
+    srcrack: &'a str,
+    dstrack: &'a str,
+    srcpod: &'a str,
+    dstpod: &'a str,
+    count: usize,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = Opt::parse();
+    match opt.command {
+        Command::FromCsv {
+            input,
+            output,
+            raw,
+            filter,
+            host_skew,
+            window,
+            weighting,
+        } => {
+            let weighting = match weighting {
+                Weight::Count => Weighting::Count,
+                Weight::Bytes => Weighting::Bytes,
+                Weight::Flows => Weighting::Flows,
+            };
+            let opts = SpatialOpts {
+                host_skew,
+                window,
+                weighting,
+            };
+            let spatial = if raw {
+                let filter: EntryFilter = match filter {
+                    Some(path) => serde_json::from_str(&fs::read_to_string(path)?)?,
+                    None => EntryFilter::default(),
+                };
+                let mut stats = FilterStats::default();
+                let entries = csv::Reader::from_path(&input)?
+                    .into_deserialize::<FullEntry>()
+                    .map(|result| anyhow::Ok(result?.into_entry_with(&filter, &mut stats)))
+                    .filter_map(Result::transpose);
+                let spatial = SpatialData::try_from_entries(entries, &opts)?;
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+                spatial
+            } else {
+                SpatialData::from_csv_with(&input, &opts)?
+            };
+            write_spatial(&output, &spatial)?;
+        }
+        Command::Downsample {
+            input,
+            output,
+            pods,
+            racks,
+            seed,
+            mode,
+            iterations,
+        } => {
+            let spatial = read_spatial(&input)?;
+            let mode = match mode {
+                Mode::Uniform => DownsampleMode::Uniform,
+                Mode::TopK => DownsampleMode::TopK,
+                Mode::Stratified => DownsampleMode::Stratified,
+                Mode::Optimized => DownsampleMode::Optimized { iterations },
+            };
+            let rng = StdRng::seed_from_u64(seed);
+            let (downsampled, comparison) = spatial.downsample_with(pods, racks, mode, rng)?;
+            let output = output.unwrap_or_else(|| {
+                let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+                input.with_file_name(format!("{stem}_{pods}_{racks}.json"))
+            });
+            write_spatial(&output, &downsampled)?;
+            println!("{}", serde_json::to_string_pretty(&comparison)?);
+        }
+        Command::Anonymize {
+            input,
+            output,
+            secret_file,
+            raw,
+            drop_ips,
+            verify,
+        } => {
+            let secret = fs::read(&secret_file)?;
+            let anonymizer = Anonymizer::builder()
+                .key(&secret)
+                .drop_ips(drop_ips)
+                .build();
+            let mut rdr = csv::Reader::from_path(&input)?;
+            let mut wtr = csv::Writer::from_path(&output)?;
+            if raw {
+                for result in rdr.deserialize() {
+                    let entry: FullEntry = result?;
+                    wtr.serialize(anonymizer.full_entry(entry))?;
+                }
+            } else {
+                for result in rdr.deserialize() {
+                    let entry: Entry = result?;
+                    wtr.serialize(anonymizer.entry(entry))?;
+                }
+            }
+            wtr.flush()?;
+            if verify {
+                // Compare against what was actually written
+                let opts = SpatialOpts::builder().host_skew(!drop_ips).build();
+                let original = SpatialData::try_from_entries(entries(&input, raw)?, &opts)?;
+                let anonymized = SpatialData::try_from_entries(entries(&output, raw)?, &opts)?;
+                anonymizer.verify(&original, &anonymized)?;
+            }
+        }
+        Command::Stats { file } => {
+            let spatial = read_spatial(&file)?;
+            let stats = Stats {
+                nr_pods: spatial.nr_pods,
+                nr_racks: spatial.nr_racks,
+                nnz: spatial.matrix.nnz(),
+                total: spatial.matrix.total(),
+                locality: LocalityFractions::new(&spatial),
+                host_skew: spatial.host_skew.is_some(),
+                nr_windows: spatial.windows.as_ref().map(|w| w.windows.len()),
+                weighting: spatial.weighting,
+            };
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        }
+        Command::ToCsv { input, output } => {
+            let spatial = read_spatial(&input)?;
+            let tor2pod = spatial
+                .pod2tors
+                .iter()
+                .flat_map(|(pod, tors)| tors.iter().map(move |tor| (tor.as_str(), pod.as_str())))
+                .collect::<FxHashMap<_, _>>();
+            let matrix = &spatial.matrix;
+            let mut wtr = csv::Writer::from_path(&output)?;
+            let pod_of = |rack: &str| match tor2pod.get(rack) {
+                Some(&pod) => Ok(pod),
+                None => anyhow::bail!("rack {rack} is not in any pod"),
+            };
+            for (src, dst, count) in matrix.entries() {
+                let srcrack = matrix.idx2name[src].as_str();
+                let dstrack = matrix.idx2name[dst].as_str();
+                wtr.serialize(MatrixRow {
+                    srcrack,
+                    dstrack,
+                    srcpod: pod_of(srcrack)?,
+                    dstpod: pod_of(dstrack)?,
+                    count,
+                })?;
+            }
+            wtr.flush()?;
+        }
+        Command::Report {
+            file,
+            against,
+            top_k,
+        } => {
+            let spatial = read_spatial(&file)?;
+            let comparison = match against {
+                Some(other) => Some(Comparison::new(&read_spatial(&other)?, &spatial)),
+                None => None,
+            };
+            let report = Report {
+                report: MatrixReport::new(&spatial, top_k),
+                comparison,
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
+    Ok(())
+}
+
+fn read_spatial(path: &PathBuf) -> anyhow::Result<SpatialData> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn write_spatial(path: &PathBuf, spatial: &SpatialData) -> anyhow::Result<()> {
+    let mut wtr = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut wtr, spatial)?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Streams the entries of a CSV trace. Raw rows that wouldn't make it into spatial data are
+/// skipped.
+fn entries(
+    path: &PathBuf,
+    raw: bool,
+) -> anyhow::Result<Box<dyn Iterator<Item = anyhow::Result<Entry>>>> {
+    let rdr = csv::Reader::from_path(path)?;
+    Ok(match raw {
+        true => Box::new(
+            rdr.into_deserialize::<FullEntry>()
+                .map(|result| anyhow::Ok(result?.into_entry()))
+                .filter_map(Result::transpose),
+        ),
+        false => Box::new(rdr.into_deserialize::<Entry>().map(|result| Ok(result?))),
+    })
+}
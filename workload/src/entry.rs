@@ -1,4 +1,8 @@
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+use std::ops::Range;
+
+use rustc_hash::FxHashSet;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FullEntry {
     pub timestamp: u64,
     pub packetlength: u64,
@@ -18,8 +22,18 @@ pub struct FullEntry {
 }
 
 impl FullEntry {
+    /// Keeps intra-cluster entries with no missing fields.
     pub fn into_entry(self) -> Option<Entry> {
-        (self.is_intracluster() && self.is_valid_enough()).then_some(Entry {
+        self.into_entry_with(&EntryFilter::default(), &mut FilterStats::default())
+    }
+
+    /// Keeps entries that pass `filter`, counting in `stats` the rule each rejected entry failed
+    /// first.
+    pub fn into_entry_with(self, filter: &EntryFilter, stats: &mut FilterStats) -> Option<Entry> {
+        if !self.passes(filter, stats) {
+            return None;
+        }
+        Some(Entry {
             timestamp: self.timestamp,
             srcip: self.srcip,
             dstip: self.dstip,
@@ -27,11 +41,53 @@ impl FullEntry {
             dstrack: self.dstrack,
             srcpod: self.srcpod,
             dstpod: self.dstpod,
+            packetlength: Some(self.packetlength),
+            srcport: Some(self.srcport),
+            dstport: Some(self.dstport),
+            ipprotocol: Some(self.ipprotocol),
         })
     }
 
-    fn is_intracluster(&self) -> bool {
-        !(self.intercluster || self.interdatacenter)
+    /// Whether this entry passes `filter`. Like [`FullEntry::into_entry_with`], counts the
+    /// outcome in `stats`.
+    pub fn passes(&self, filter: &EntryFilter, stats: &mut FilterStats) -> bool {
+        let rejected_by = if (self.intercluster && !filter.include_intercluster)
+            || (self.interdatacenter && !filter.include_interdatacenter)
+        {
+            Some(&mut stats.intercluster)
+        } else if !self.is_valid_enough() {
+            Some(&mut stats.missing_fields)
+        } else if filter
+            .protocols
+            .as_ref()
+            .is_some_and(|protocols| !protocols.contains(&self.ipprotocol))
+        {
+            Some(&mut stats.protocol)
+        } else if filter
+            .dst_ports
+            .as_ref()
+            .is_some_and(|ports| !ports.contains(&self.dstport))
+        {
+            Some(&mut stats.port)
+        } else if filter
+            .time_range
+            .as_ref()
+            .is_some_and(|range| !range.contains(&self.timestamp))
+        {
+            Some(&mut stats.time_range)
+        } else {
+            None
+        };
+        match rejected_by {
+            Some(count) => {
+                *count += 1;
+                false
+            }
+            None => {
+                stats.accepted += 1;
+                true
+            }
+        }
     }
 
     fn is_valid_enough(&self) -> bool {
@@ -45,7 +101,46 @@ impl FullEntry {
     }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// Which [`FullEntry`]s to keep. The default keeps intra-cluster traffic of any protocol, port
+/// and time. Entries with missing fields are always dropped.
+#[derive(
+    Debug, Clone, Default, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize,
+)]
+#[serde(default)]
+pub struct EntryFilter {
+    #[builder(default)]
+    pub include_intercluster: bool,
+    #[builder(default)]
+    pub include_interdatacenter: bool,
+    /// Only keep entries whose `ipprotocol` is in this set.
+    #[builder(default, setter(strip_option))]
+    pub protocols: Option<FxHashSet<String>>,
+    /// Only keep entries whose `dstport` is in this set.
+    #[builder(default, setter(strip_option))]
+    pub dst_ports: Option<FxHashSet<String>>,
+    /// Only keep entries whose `timestamp` falls in this range.
+    #[builder(default, setter(strip_option))]
+    pub time_range: Option<Range<u64>>,
+}
+
+/// How many entries were kept, and how many were rejected by each rule of an [`EntryFilter`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct FilterStats {
+    pub accepted: usize,
+    pub intercluster: usize,
+    pub missing_fields: usize,
+    pub protocol: usize,
+    pub port: usize,
+    pub time_range: usize,
+}
+
+impl FilterStats {
+    pub fn rejected(&self) -> usize {
+        self.intercluster + self.missing_fields + self.protocol + self.port + self.time_range
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct Entry {
     pub timestamp: u64,
     pub srcip: String,
@@ -54,4 +149,81 @@ pub struct Entry {
     pub dstrack: String,
     pub srcpod: String,
     pub dstpod: String,
+    /// Only needed for byte-weighted matrices. Older CSVs without these columns still load.
+    #[serde(default)]
+    pub packetlength: Option<u64>,
+    /// Only needed for flow-weighted matrices.
+    #[serde(default)]
+    pub srcport: Option<String>,
+    #[serde(default)]
+    pub dstport: Option<String>,
+    #[serde(default)]
+    pub ipprotocol: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_entry(timestamp: u64, protocol: &str, port: &str, intercluster: bool) -> FullEntry {
+        FullEntry {
+            timestamp,
+            packetlength: 1500,
+            srcip: "a".to_string(),
+            dstip: "b".to_string(),
+            srcport: "1234".to_string(),
+            dstport: port.to_string(),
+            ipprotocol: protocol.to_string(),
+            srchostprefix: "a".to_string(),
+            dsthostprefix: "b".to_string(),
+            srcrack: "r0".to_string(),
+            dstrack: "r1".to_string(),
+            srcpod: "p0".to_string(),
+            dstpod: "p0".to_string(),
+            intercluster,
+            interdatacenter: false,
+        }
+    }
+
+    #[test]
+    fn filter_rejections_counted() {
+        let mut missing = full_entry(0, "6", "443", false);
+        missing.srcrack = "\\N".to_string();
+        let entries = vec![
+            full_entry(0, "6", "443", false),
+            full_entry(1, "6", "443", true),
+            missing,
+            full_entry(2, "17", "443", false),
+            full_entry(3, "6", "80", false),
+            full_entry(10, "6", "443", false),
+        ];
+        let filter = EntryFilter::builder()
+            .protocols(["6".to_string()].into_iter().collect())
+            .dst_ports(["443".to_string()].into_iter().collect())
+            .time_range(0..10)
+            .build();
+        let mut stats = FilterStats::default();
+        let kept = entries
+            .into_iter()
+            .filter_map(|e| e.into_entry_with(&filter, &mut stats))
+            .collect::<Vec<_>>();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(
+            stats,
+            FilterStats {
+                accepted: 1,
+                intercluster: 1,
+                missing_fields: 1,
+                protocol: 1,
+                port: 1,
+                time_range: 1,
+            }
+        );
+        assert_eq!(stats.rejected(), 5);
+
+        // Including inter-cluster traffic lets it through
+        let filter = EntryFilter::builder().include_intercluster(true).build();
+        let entry = full_entry(1, "6", "443", true);
+        assert!(entry.into_entry_with(&filter, &mut stats).is_some());
+    }
 }
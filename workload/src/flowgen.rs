@@ -1,151 +1,687 @@
+use std::sync::Arc;
+
 use crate::{
     fabric::{Cluster, FabricRoutes},
-    spatial::{SpatialData, SpatialWorkload},
+    spatial::{self, Locality, SpatialData, SpatialWorkload},
 };
 use parsimon::core::{
-    network::{Channel, Flow, FlowId, Network},
-    units::{BitsPerSec, Bytes, Nanosecs, Secs},
+    network::{Flow, FlowId},
+    units::{Bytes, Nanosecs, Secs},
 };
 use rand::prelude::*;
-use rand_distr::LogNormal;
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
 use utils::Ecdf;
 
+mod arrivals;
+mod calibration;
+mod envelope;
+mod groups;
+mod load;
+mod meta;
+mod mixture;
+mod replay;
+mod report;
+
+pub use arrivals::{ArrivalProcess, Arrivals, MmppState};
+pub use calibration::Calibration;
+pub use envelope::LoadEnvelope;
+pub use groups::{GroupGenerator, GroupPattern, GroupedFlows};
+pub use load::{ChannelLoad, LoadProfile, LoadTarget, Tier};
+pub use meta::{FlowMeta, FlowMetaTable};
+pub use mixture::{MixedFlows, Mixture, WorkloadClass};
+pub use replay::TraceReplay;
+pub use report::{LocalityFractions, ReportTargets, TierLoad, Tolerances, WorkloadReport};
+
 #[derive(Debug, typed_builder::TypedBuilder)]
 pub struct FlowGenerator {
+    /// Matrices weighted by bytes or sampled packets are converted to flow arrival weights by
+    /// dividing each cell by the mean flow size of its locality; flow-weighted ones are sampled
+    /// from as is.
     spatial_data: SpatialData,
     cluster: Cluster,
     size_dist: Ecdf,
+    /// Size distributions for flows of particular localities, overriding `size_dist`.
+    #[builder(default)]
+    locality_size_dists: FxHashMap<Locality, Ecdf>,
+    /// The sigma of the default, lognormal inter-arrival times. Ignored if `arrivals` is set.
     lognorm_sigma: f64,
+    /// How inter-arrival times are distributed, overriding the lognormal default.
+    #[builder(default, setter(strip_option))]
+    arrivals: Option<ArrivalProcess>,
     #[builder(default = Secs::ONE)]
     start_time: Secs,
-    max_load: f64,
+    /// The load to scale the arrival rate to. A plain number targets the most loaded channel.
+    #[builder(setter(into))]
+    load_target: LoadTarget,
     stop_when: StopWhen,
     #[builder(default = FlowId::ZERO)]
     id_start: FlowId,
     #[builder(default = 0)]
     seed: u64,
+    /// Simulated time spanned by one time window of the spatial data. If set and the spatial
+    /// data has windows, the sampling matrix follows the windows as simulated time advances.
+    /// Load calibration always uses the matrix of the whole trace.
+    #[builder(default, setter(strip_option))]
+    window_duration: Option<Nanosecs>,
+    /// Indices of the cluster pods to place the spatial data's pods on. Defaults to all pods.
+    #[builder(default, setter(strip_option))]
+    pods: Option<Vec<usize>>,
+    #[builder(default)]
+    calibration: Calibration,
+    /// Varies the arrival rate over time. Without it, the rate is constant.
+    #[builder(default, setter(strip_option))]
+    envelope: Option<LoadEnvelope>,
+    /// Time to generate flows for before measurement starts, so that measurement flows don't
+    /// see an empty network. `stop_when` counts from the end of the warm-up.
+    #[builder(default, setter(into))]
+    warm_up: Nanosecs,
+    /// Time to keep generating flows for after measurement ends, so that measurement flows
+    /// aren't the last in the network.
+    #[builder(default, setter(into))]
+    cool_down: Nanosecs,
+}
+
+/// The settings of a single generation run that a [`Mixture`] overrides per class.
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    start_time: Secs,
+    load_target: LoadTarget,
+    stop_when: StopWhen,
+    warm_up: Nanosecs,
+    cool_down: Nanosecs,
+    id_start: FlowId,
+    seed: u64,
 }
 
 impl FlowGenerator {
-    pub fn generate(&self) -> Vec<Flow> {
-        let mut rng = StdRng::seed_from_u64(self.seed);
+    pub fn generate(&self) -> Result<Vec<Flow>, Error> {
+        let (flows, _) = self.generate_with_profile()?;
+        Ok(flows)
+    }
+
+    /// Like [`FlowGenerator::generate`], also returning the per-channel loads the flows are
+    /// calibrated to produce.
+    pub fn generate_with_profile(&self) -> Result<(Vec<Flow>, LoadProfile), Error> {
+        let (flows, profile) = self.iter_with_profile()?;
+        Ok((flows.collect(), profile))
+    }
+
+    /// Like [`FlowGenerator::generate`], also reporting how the flows compare to the size
+    /// distribution, arrival rate, and channel loads they were generated for. The size
+    /// distribution is only compared against if no locality overrides it, and the arrival rate
+    /// only without an envelope or warm-up.
+    pub fn generate_with_report(&self) -> Result<(Vec<Flow>, WorkloadReport), Error> {
+        let (flows, profile) = self.iter_with_profile()?;
+        let steady = self.envelope.is_none() && self.warm_up == Nanosecs::ZERO;
+        let mean_inter_arrival = Nanosecs::new(flows.mean_i.round() as u64);
+        let flows = flows.collect::<Vec<_>>();
+        let targets = ReportTargets {
+            size_dist: self
+                .locality_size_dists
+                .is_empty()
+                .then(|| self.size_dist.clone()),
+            mean_inter_arrival: steady.then_some(mean_inter_arrival),
+            profile: Some(profile),
+        };
+        let report = WorkloadReport::new(&flows, &self.cluster, &targets)?;
+        Ok((flows, report))
+    }
+
+    /// Like [`FlowGenerator::generate`], also returning the window of measurement flow start
+    /// times.
+    pub fn generate_with_window(&self) -> Result<(Vec<Flow>, MeasurementWindow), Error> {
+        let mut flows = self.iter()?;
+        let generated = flows.by_ref().collect();
+        Ok((generated, flows.measurement_window()))
+    }
+
+    /// Generates the same flows as [`FlowGenerator::generate`], but lazily. Calibration happens
+    /// up front; each flow is drawn only when the iterator is advanced.
+    pub fn iter(&self) -> Result<Flows<'_>, Error> {
+        let (flows, _) = self.iter_with_profile()?;
+        Ok(flows)
+    }
+
+    /// Like [`FlowGenerator::iter`], also returning the per-channel loads the flows are
+    /// calibrated to produce.
+    pub fn iter_with_profile(&self) -> Result<(Flows<'_>, LoadProfile), Error> {
+        self.run(Run {
+            start_time: self.start_time,
+            load_target: self.load_target,
+            stop_when: self.stop_when,
+            warm_up: self.warm_up,
+            cool_down: self.cool_down,
+            id_start: self.id_start,
+            seed: self.seed,
+        })
+    }
+
+    fn run(&self, run: Run) -> Result<(Flows<'_>, LoadProfile), Error> {
+        if let Some(envelope) = &self.envelope {
+            envelope.validate()?;
+        }
+        let mut rng = StdRng::seed_from_u64(run.seed);
 
-        // Get the spatial workload
-        let spatial_wk = self.spatial_data.map_to(&self.cluster, &mut rng).unwrap();
+        // Get the spatial workload. Flow arrivals are sampled in proportion to `flow_scale`
+        // times cell weights. For calibration, cells are first sampled in proportion to the
+        // bytes they carry, relative to a flow of mean `size_dist` size.
+        let mean_sizes = [Locality::IntraRack, Locality::IntraPod, Locality::InterPod]
+            .into_iter()
+            .map(|locality| (locality, self.size_dist_for(locality).mean()))
+            .collect::<FxHashMap<_, _>>();
+        let is_load = self.spatial_data.weighting.is_load();
+        let flow_scale = |locality| match is_load {
+            true => mean_sizes[&locality].recip(),
+            false => 1.0,
+        };
+        let byte_scale =
+            |locality| flow_scale(locality) * mean_sizes[&locality] / self.size_dist.mean();
+        let mut spatial_wk = match &self.pods {
+            Some(pods) => {
+                self.spatial_data
+                    .map_onto_pods(&self.cluster, pods, byte_scale, &mut rng)
+            }
+            None => self
+                .spatial_data
+                .map_to_with(&self.cluster, byte_scale, &mut rng),
+        }?;
 
-        // Compute the rate required to achieve the specified max link load
-        let nr_test_flows = match self.stop_when {
-            StopWhen::Elapsed(_) => self.cluster.links().count() * 10_000,
-            StopWhen::NrFlows(nr_flows) => nr_flows,
+        // Compute the rate required to achieve the specified load
+        let fractions = match self.calibration {
+            Calibration::Analytic => {
+                calibration::channel_fractions(&spatial_wk, &FabricRoutes::new(&self.cluster))
+            }
+            Calibration::Sampled => {
+                let nr_test_flows = match run.stop_when {
+                    StopWhen::Elapsed(_) => self.cluster.links().count() * 10_000,
+                    StopWhen::NrFlows(nr_flows) => nr_flows,
+                };
+                calibration::sampled_channel_fractions(
+                    &spatial_wk,
+                    &self.cluster,
+                    nr_test_flows,
+                    &mut rng,
+                )
+            }
         };
-        let chan = Self::most_loaded_channel(&spatial_wk, &self.cluster, nr_test_flows, &mut rng);
-        let total_rate = chan
-            .bandwidth
-            .scale_by(self.max_load)
-            .scale_by(chan.frac.recip());
+        let (total_rate, profile) = load::calibrate(&self.cluster, &fractions, run.load_target)?;
 
         // Get inter-arrival distribution
-        let mean_f = Bytes::new(self.size_dist.mean().round() as u64);
+        spatial_wk.rescale(flow_scale)?;
+        let mean_f = spatial_wk
+            .locality_fractions()
+            .into_iter()
+            .map(|(locality, frac)| frac * mean_sizes[&locality])
+            .sum::<f64>();
+        let mean_f = Bytes::new(mean_f.round() as u64);
         let mean_i = utils::mean_i_for_r(total_rate, mean_f);
-        let lognorm_mu = utils::lognorm_mean_to_mu(mean_i.into_f64(), self.lognorm_sigma);
-        let delta_dist = LogNormal::new(lognorm_mu, self.lognorm_sigma).unwrap();
+        let arrivals = match &self.arrivals {
+            Some(arrivals) => arrivals.clone(),
+            None => ArrivalProcess::LogNormal {
+                sigma: self.lognorm_sigma,
+            },
+        };
+        let arrivals = arrivals.arrivals(mean_i)?;
+        let mean_i = mean_i.into_f64();
 
         // Generate flows
-        Self::do_generate(
-            &spatial_wk,
-            &self.size_dist,
-            delta_dist,
-            self.start_time,
-            self.stop_when,
-            self.id_start,
-            &mut rng,
-        )
-    }
-
-    fn most_loaded_channel(
-        spatial_wk: &SpatialWorkload,
-        cluster: &Cluster,
-        nr_test_flows: usize,
-        mut rng: impl Rng,
-    ) -> ChannelInfo {
-        let nodes = cluster.nodes().cloned().collect::<Vec<_>>();
-        let links = cluster.links().cloned().collect::<Vec<_>>();
-        let network = Network::new_with_routes(&nodes, &links, FabricRoutes::new(cluster))
-            .expect("invalid cluster specification");
-        let flows = (0..nr_test_flows)
-            .map(|i| {
-                let (src, dst) = spatial_wk.sample(&mut rng);
-                Flow {
-                    id: FlowId::new(i),
-                    src,
-                    dst,
-                    size: Bytes::default(),
-                    start: Nanosecs::default(),
-                }
+        let start_time: Nanosecs = run.start_time.into();
+        let measure_start = start_time + run.warm_up;
+        let (end, max_nr_flows) = match run.stop_when {
+            StopWhen::Elapsed(duration) => {
+                (measure_start + duration.into() + run.cool_down, usize::MAX)
+            }
+            StopWhen::NrFlows(0) => (start_time, 0),
+            StopWhen::NrFlows(max_nr_flows) => (Nanosecs::MAX, max_nr_flows),
+        };
+        let flows = Flows {
+            generator: self,
+            spatial_wk: Arc::new(spatial_wk),
+            arrivals,
+            mean_i,
+            rng,
+            start_time,
+            stop_when: run.stop_when,
+            cur: start_time,
+            end,
+            measure_start,
+            measure_end: match run.stop_when {
+                StopWhen::Elapsed(duration) => measure_start + duration.into(),
+                StopWhen::NrFlows(_) => measure_start,
+            },
+            cool_down: run.cool_down,
+            id_start: run.id_start,
+            nr_flows: 0,
+            nr_measured: 0,
+            max_nr_flows,
+        };
+        Ok((flows, profile))
+    }
+
+    /// Generates flows in `nr_chunks` independent chunks, in parallel. With
+    /// [`StopWhen::Elapsed`], each chunk covers an equal slice of the time horizon; with
+    /// [`StopWhen::NrFlows`], an equal share of the flows, starting where that many flows are
+    /// expected to have arrived, following the envelope if there is one. Only the first chunk
+    /// warms up and only the last cools down. Each chunk restarts the arrival process with its
+    /// own seed, drawn after calibration. Chunks are merged in order of start time and
+    /// renumbered, and the window of measurement flows spans those of all chunks.
+    ///
+    /// The flows depend on the seed and `nr_chunks` only, not on the number of threads or on
+    /// scheduling, but differ from those of [`FlowGenerator::generate`].
+    pub fn generate_parallel(
+        &self,
+        nr_chunks: usize,
+    ) -> Result<(Vec<Flow>, MeasurementWindow), Error> {
+        if nr_chunks == 0 {
+            return Err(Error::NoChunks);
+        }
+        let (mut flows, _) = self.iter_with_profile()?;
+        let seeds = (0..nr_chunks)
+            .map(|_| flows.rng.gen())
+            .collect::<Vec<u64>>();
+        let per_chunk = seeds
+            .into_par_iter()
+            .enumerate()
+            .map(|(i, seed)| {
+                let mut chunk = flows.chunk(i, nr_chunks, seed);
+                let generated = chunk.by_ref().collect::<Vec<_>>();
+                (generated, chunk.measurement_window())
             })
             .collect::<Vec<_>>();
-        let network = network.into_simulations(flows);
-        let (nr_link_flows, bandwidth) = network
-            .channels()
-            .map(|chan| {
-                let nr_flows = chan.nr_flows();
-                let bandwidth = chan.bandwidth();
-                let nr_flows_per_gbps = nr_flows as f64 / (bandwidth.into_f64() / 1e9);
-                (nr_flows_per_gbps, nr_flows, bandwidth)
-            })
-            .max_by(|(a, _, _), (b, _, _)| a.partial_cmp(b).unwrap())
-            .map(|(_, nr_flows, bandwidth)| (nr_flows, bandwidth))
-            .unwrap();
-        ChannelInfo {
-            bandwidth,
-            frac: nr_link_flows as f64 / nr_test_flows as f64,
+        let window = MeasurementWindow {
+            start: per_chunk[0].1.start,
+            end: per_chunk[nr_chunks - 1].1.end,
+        };
+        let mut merged = per_chunk
+            .into_iter()
+            .flat_map(|(flows, _)| flows)
+            .collect::<Vec<_>>();
+        // The sort is stable, so flows with equal start times stay in chunk order
+        merged.sort_by_key(|flow| flow.start);
+        for (i, flow) in merged.iter_mut().enumerate() {
+            flow.id = self.id_start + FlowId::new(i);
         }
+        Ok((merged, window))
     }
 
-    fn do_generate(
-        spatial_wk: &SpatialWorkload,
-        size_dist: impl Distribution<f64>,
-        delta_dist: impl Distribution<f64>,
-        start_time: Secs,
-        stop_when: StopWhen,
-        id_start: FlowId,
-        mut rng: impl Rng,
-    ) -> Vec<Flow> {
-        let start_time: Nanosecs = start_time.into();
-        let mut flows = Vec::new();
-        let mut nr_flows = 0;
-        let mut cur = start_time;
-        let (end, max_nr_flows) = match stop_when {
-            StopWhen::Elapsed(duration) => (start_time + duration.into(), usize::MAX),
-            StopWhen::NrFlows(max_nr_flows) => (Nanosecs::MAX, max_nr_flows),
+    fn size_dist_for(&self, locality: Locality) -> &Ecdf {
+        self.locality_size_dists
+            .get(&locality)
+            .unwrap_or(&self.size_dist)
+    }
+}
+
+/// Flows drawn lazily in order of start time, created by [`FlowGenerator::iter`].
+#[derive(Debug)]
+pub struct Flows<'a> {
+    generator: &'a FlowGenerator,
+    spatial_wk: Arc<SpatialWorkload>,
+    arrivals: Arrivals,
+    // The mean inter-arrival time, in nanoseconds
+    mean_i: f64,
+    rng: StdRng,
+    start_time: Nanosecs,
+    stop_when: StopWhen,
+    cur: Nanosecs,
+    end: Nanosecs,
+    measure_start: Nanosecs,
+    // With `StopWhen::NrFlows`, this is only known once the last measurement flow is drawn
+    measure_end: Nanosecs,
+    cool_down: Nanosecs,
+    id_start: FlowId,
+    nr_flows: usize,
+    // The number of measurement flows drawn, and how many to draw
+    nr_measured: usize,
+    max_nr_flows: usize,
+}
+
+impl Flows<'_> {
+    /// The start times of measurement flows. With [`StopWhen::NrFlows`], this is only complete
+    /// once the iterator is exhausted.
+    pub fn measurement_window(&self) -> MeasurementWindow {
+        MeasurementWindow {
+            start: self.measure_start,
+            end: self.measure_end,
+        }
+    }
+
+    /// The `i`th of `nr_chunks` independent parts of these flows, seeded with `seed`.
+    fn chunk(&self, i: usize, nr_chunks: usize, seed: u64) -> Self {
+        let part = |total: u64, i: usize| (total as u128 * i as u128 / nr_chunks as u128) as u64;
+        let (cur, end, max_nr_flows) = match self.stop_when {
+            StopWhen::Elapsed(_) => {
+                let horizon = self.end.into_u64() - self.cur.into_u64();
+                let cur = self.cur + Nanosecs::new(part(horizon, i));
+                let end = self.cur + Nanosecs::new(part(horizon, i + 1));
+                (cur, end, usize::MAX)
+            }
+            StopWhen::NrFlows(nr_flows) => {
+                // Only the first chunk warms up
+                let (first, last) = (part(nr_flows as u64, i), part(nr_flows as u64, i + 1));
+                let cur = match i {
+                    0 => self.cur,
+                    _ => self.advance(self.measure_start, first as f64 * self.mean_i),
+                };
+                let end = match last > first {
+                    true => self.end,
+                    false => cur,
+                };
+                (cur, end, (last - first) as usize)
+            }
         };
-        while cur < end && nr_flows < max_nr_flows {
-            let (src, dst) = spatial_wk.sample(&mut rng);
-            let size = Bytes::new(size_dist.sample(&mut rng).round() as u64);
-            let delta = Nanosecs::new(delta_dist.sample(&mut rng).round() as u64);
-            let flow = Flow {
-                id: id_start + FlowId::new(flows.len()),
-                src,
-                dst,
-                size,
-                start: cur,
-            };
-            flows.push(flow);
-            nr_flows += 1;
-            cur += delta;
+        let measure_start = self.measure_start.max(cur);
+        // Only the last chunk cools down
+        let cool_down = match i + 1 == nr_chunks {
+            true => self.cool_down,
+            false => Nanosecs::ZERO,
+        };
+        Self {
+            generator: self.generator,
+            spatial_wk: Arc::clone(&self.spatial_wk),
+            arrivals: self.arrivals.clone(),
+            mean_i: self.mean_i,
+            rng: StdRng::seed_from_u64(seed),
+            start_time: self.start_time,
+            stop_when: self.stop_when,
+            cur,
+            end,
+            measure_start,
+            measure_end: match self.stop_when {
+                StopWhen::Elapsed(_) => self.measure_end,
+                StopWhen::NrFlows(_) => measure_start,
+            },
+            cool_down,
+            id_start: self.id_start,
+            nr_flows: 0,
+            nr_measured: 0,
+            max_nr_flows,
         }
-        flows
+    }
+
+    /// The simulated time at which `work` nanoseconds of operational time have passed since `t`.
+    fn advance(&self, t: Nanosecs, work: f64) -> Nanosecs {
+        match &self.generator.envelope {
+            Some(envelope) => {
+                let offset = (t - self.start_time).into_f64();
+                let offset = envelope.advance(offset, work);
+                self.start_time + Nanosecs::new(offset.round() as u64)
+            }
+            None => t + Nanosecs::new(work.round() as u64),
+        }
+    }
+}
+
+impl Iterator for Flows<'_> {
+    type Item = Flow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.cur >= self.end {
+            return None;
+        }
+        let rng = &mut self.rng;
+        let (src, dst, locality) = match self.generator.window_duration {
+            Some(duration) => {
+                let elapsed = self.cur.into_f64() - self.start_time.into_f64();
+                let slot = elapsed / duration.into_f64();
+                self.spatial_wk
+                    .sample_at_with_locality(slot as usize, &mut *rng)
+            }
+            None => self.spatial_wk.sample_with_locality(&mut *rng),
+        };
+        let size = self.generator.size_dist_for(locality).sample(&mut *rng);
+        let size = Bytes::new(size.round() as u64);
+        let delta = self.arrivals.next_delta(&mut *rng);
+        let flow = Flow {
+            id: self.id_start + FlowId::new(self.nr_flows),
+            src,
+            dst,
+            size,
+            start: self.cur,
+        };
+        self.nr_flows += 1;
+        if let StopWhen::NrFlows(_) = self.stop_when {
+            if flow.start >= self.measure_start && self.nr_measured < self.max_nr_flows {
+                self.nr_measured += 1;
+                self.measure_end = flow.start + Nanosecs::ONE;
+                if self.nr_measured == self.max_nr_flows {
+                    self.end = flow.start + self.cool_down;
+                }
+            }
+        }
+        self.cur = self.advance(self.cur, delta);
+        Some(flow)
     }
 }
 
+/// When to stop generating measurement flows. Warm-up and cool-down flows come on top.
 #[derive(Debug, Clone, Copy)]
 pub enum StopWhen {
     Elapsed(Secs),
     NrFlows(usize),
 }
 
-#[derive(Debug)]
-struct ChannelInfo {
-    bandwidth: BitsPerSec,
-    frac: f64,
+/// The half-open range of start times of measurement flows. Flows starting outside it are
+/// warm-up or cool-down flows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MeasurementWindow {
+    pub start: Nanosecs,
+    pub end: Nanosecs,
+}
+
+impl MeasurementWindow {
+    /// A window containing every flow.
+    pub const ALL: MeasurementWindow = MeasurementWindow {
+        start: Nanosecs::ZERO,
+        end: Nanosecs::MAX,
+    };
+
+    pub fn contains(&self, flow: &Flow) -> bool {
+        (self.start..self.end).contains(&flow.start)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("parallel generation needs at least one chunk")]
+    NoChunks,
+
+    #[error(transparent)]
+    Spatial(#[from] spatial::Error),
+
+    #[error(transparent)]
+    Arrivals(#[from] arrivals::Error),
+
+    #[error(transparent)]
+    Envelope(#[from] envelope::Error),
+
+    #[error(transparent)]
+    Load(#[from] load::Error),
+
+    #[error(transparent)]
+    Report(#[from] report::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entry::Entry, spatial::SpatialOpts, testing::TINY_CLUSTER};
+
+    fn generator(stop_when: StopWhen) -> anyhow::Result<FlowGenerator> {
+        generator_with(stop_when, None)
+    }
+
+    fn generator_with(
+        stop_when: StopWhen,
+        envelope: Option<LoadEnvelope>,
+    ) -> anyhow::Result<FlowGenerator> {
+        let racks = [("r0", "p0"), ("r1", "p0"), ("r2", "p1"), ("r3", "p1")];
+        let entries = racks.iter().flat_map(|&(src, srcpod)| {
+            racks.iter().map(move |&(dst, dstpod)| Entry {
+                srcrack: src.to_string(),
+                dstrack: dst.to_string(),
+                srcpod: srcpod.to_string(),
+                dstpod: dstpod.to_string(),
+                ..Default::default()
+            })
+        });
+        let generator = FlowGenerator::builder()
+            .spatial_data(SpatialData::from_entries(entries, &SpatialOpts::default())?)
+            .cluster(serde_json::from_str(TINY_CLUSTER)?)
+            .size_dist(Ecdf::from_values(&[1000.0, 10_000.0])?)
+            .lognorm_sigma(1.0)
+            .load_target(0.001)
+            .stop_when(stop_when)
+            .seed(7)
+            .build();
+        Ok(FlowGenerator {
+            envelope,
+            ..generator
+        })
+    }
+
+    fn summary(flows: &[Flow]) -> Vec<(usize, usize, usize, u64, u64)> {
+        flows
+            .iter()
+            .map(|f| {
+                let (src, dst) = (f.src.inner(), f.dst.inner());
+                (
+                    f.id.inner(),
+                    src,
+                    dst,
+                    f.size.into_u64(),
+                    f.start.into_u64(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_generation_reproducible() -> anyhow::Result<()> {
+        for stop_when in [StopWhen::NrFlows(10_000), StopWhen::Elapsed(Secs::new(1))] {
+            let generator = generator(stop_when)?;
+            let (flows, _) = generator.generate_parallel(4)?;
+            let single = rayon::ThreadPoolBuilder::new()
+                .num_threads(1)
+                .build()?
+                .install(|| generator.generate_parallel(4))?
+                .0;
+            assert_eq!(summary(&flows), summary(&single));
+            assert!(flows.windows(2).all(|w| w[0].start <= w[1].start));
+            assert!(flows.iter().enumerate().all(|(i, f)| f.id.inner() == i));
+            if let StopWhen::NrFlows(nr_flows) = stop_when {
+                assert_eq!(flows.len(), nr_flows);
+            }
+            assert!(matches!(
+                generator.generate_parallel(0),
+                Err(Error::NoChunks)
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn envelope_modulates_rate() -> anyhow::Result<()> {
+        let half = Nanosecs::new(500_000_000);
+        let envelope = LoadEnvelope::Steps {
+            steps: vec![(half, 3.0)],
+        };
+        let generator = generator_with(StopWhen::Elapsed(Secs::new(1)), Some(envelope))?;
+        let flows = generator.generate()?;
+        let start: Nanosecs = Secs::ONE.into();
+        let nr_early = flows.iter().filter(|f| f.start < start + half).count();
+        let ratio = (flows.len() - nr_early) as f64 / nr_early as f64;
+        assert!((ratio - 3.0).abs() < 0.3, "ratio {ratio}");
+        Ok(())
+    }
+
+    #[test]
+    fn parallel_generation_follows_envelope() -> anyhow::Result<()> {
+        // The rate triples once half of the flows are expected to have arrived
+        let nr_flows = 10_000;
+        let mean_i = generator(StopWhen::NrFlows(nr_flows))?.iter()?.mean_i;
+        let half = Nanosecs::new((nr_flows as f64 / 2.0 * mean_i).round() as u64);
+        let envelope = LoadEnvelope::Steps {
+            steps: vec![(half, 3.0)],
+        };
+        let generator = generator_with(StopWhen::NrFlows(nr_flows), Some(envelope))?;
+        let span = |flows: &[Flow]| (flows.last().unwrap().start - flows[0].start).into_f64();
+        let sequential = span(&generator.generate()?);
+        let parallel = span(&generator.generate_parallel(4)?.0);
+        assert!(
+            (parallel - sequential).abs() < 0.05 * sequential,
+            "parallel {parallel}, sequential {sequential}"
+        );
+
+        let invalid = LoadEnvelope::Steps { steps: vec![] };
+        let generator = generator_with(StopWhen::NrFlows(nr_flows), Some(invalid))?;
+        assert!(matches!(generator.generate(), Err(Error::Envelope(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn sampled_packets_weighted_as_load() -> anyhow::Result<()> {
+        // Half of the sampled packets cross pods, but inter-pod flows are ten times larger
+        let scales = [
+            (Locality::IntraRack, 1.0),
+            (Locality::IntraPod, 1.0),
+            (Locality::InterPod, 10.0),
+        ];
+        let locality_size_dists = scales
+            .into_iter()
+            .map(|(locality, scale)| {
+                let sizes = [1000.0 * scale, 10_000.0 * scale];
+                Ok((locality, Ecdf::from_values(&sizes)?))
+            })
+            .collect::<Result<_, utils::EcdfError>>()?;
+        let generator = FlowGenerator {
+            locality_size_dists,
+            ..generator(StopWhen::NrFlows(10_000))?
+        };
+        assert_eq!(generator.spatial_data.weighting, spatial::Weighting::Count);
+        let (_, report) = generator.generate_with_report()?;
+        assert!((report.localities.inter_pod - 1.0 / 11.0).abs() < 0.02);
+        Ok(())
+    }
+
+    #[test]
+    fn warm_up_and_cool_down_marked() -> anyhow::Result<()> {
+        let (warm_up, cool_down) = (Nanosecs::new(100_000_000), Nanosecs::new(200_000_000));
+        let start: Nanosecs = Secs::ONE.into();
+        for stop_when in [StopWhen::NrFlows(1000), StopWhen::Elapsed(Secs::new(1))] {
+            let generator = FlowGenerator {
+                warm_up,
+                cool_down,
+                ..generator(stop_when)?
+            };
+            let (flows, window) = generator.generate_with_window()?;
+            assert_eq!(window.start, start + warm_up);
+            let (_, report) = generator.generate_with_report()?;
+            assert_eq!(report.target_mean_inter_arrival, None);
+            let (parallel, parallel_window) = generator.generate_parallel(4)?;
+            assert_eq!(parallel_window.start, window.start);
+            let nr_parallel_measured = parallel
+                .iter()
+                .filter(|f| parallel_window.contains(f))
+                .count();
+            let nr_measured = flows.iter().filter(|f| window.contains(f)).count();
+            let nr_warm_up = flows.iter().filter(|f| f.start < window.start).count();
+            assert!(nr_warm_up > 0 && nr_warm_up + nr_measured < flows.len());
+            let last = flows.last().unwrap().start;
+            match stop_when {
+                StopWhen::NrFlows(nr_flows) => {
+                    assert_eq!(nr_measured, nr_flows);
+                    assert_eq!(nr_parallel_measured, nr_flows);
+                    assert!(last < window.end + cool_down);
+                }
+                StopWhen::Elapsed(duration) => {
+                    assert_eq!(window.end, window.start + duration.into());
+                    assert_eq!(parallel_window.end, window.end);
+                    assert!(nr_parallel_measured > 0);
+                    assert!(last < window.end + cool_down);
+                }
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,316 @@
+use std::f64::consts::PI;
+
+use parsimon::core::units::Nanosecs;
+use rand::prelude::*;
+use rand_distr::{Exp, LogNormal, Pareto, Weibull};
+
+/// How flow inter-arrival times are distributed. Every process is calibrated to the same mean
+/// inter-arrival time, so they differ only in burstiness.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ArrivalProcess {
+    /// Exponential inter-arrival times.
+    Poisson,
+    /// Lognormal inter-arrival times. Larger `sigma` is burstier.
+    LogNormal { sigma: f64 },
+    /// Pareto inter-arrival times. `shape` must exceed 1 for the mean to exist; values close to
+    /// 1 are very heavy-tailed.
+    Pareto { shape: f64 },
+    /// Weibull inter-arrival times. A `shape` below 1 is burstier than Poisson.
+    Weibull { shape: f64 },
+    /// Poisson arrivals during on periods and none during off periods. Period lengths are
+    /// exponentially distributed.
+    OnOff {
+        mean_on: Nanosecs,
+        mean_off: Nanosecs,
+    },
+    /// Markov-modulated Poisson: Poisson arrivals at a rate that depends on a hidden state. Each
+    /// state is held for an exponentially distributed time, after which a different state is
+    /// chosen uniformly at random.
+    Mmpp { states: Vec<MmppState> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MmppState {
+    /// Arrival rate relative to the other states. Absolute rates are set by calibration.
+    pub relative_rate: f64,
+    pub mean_sojourn: Nanosecs,
+}
+
+impl ArrivalProcess {
+    /// Returns a sampler of inter-arrival times, in nanoseconds, with mean `mean_i`.
+    pub fn arrivals(&self, mean_i: Nanosecs) -> Result<Arrivals, Error> {
+        let mean = mean_i.into_f64();
+        let arrivals = match self {
+            ArrivalProcess::Poisson => Arrivals::Exp(Exp::new(mean.recip())?),
+            ArrivalProcess::LogNormal { sigma } => {
+                let mu = utils::lognorm_mean_to_mu(mean, *sigma);
+                Arrivals::LogNormal(LogNormal::new(mu, *sigma)?)
+            }
+            ArrivalProcess::Pareto { shape } => {
+                if *shape <= 1.0 {
+                    return Err(Error::InfiniteMean);
+                }
+                let scale = mean * (shape - 1.0) / shape;
+                Arrivals::Pareto(Pareto::new(scale, *shape)?)
+            }
+            ArrivalProcess::Weibull { shape } => {
+                let scale = mean / gamma(1.0 + shape.recip());
+                Arrivals::Weibull(Weibull::new(scale, *shape)?)
+            }
+            ArrivalProcess::OnOff { mean_on, mean_off } => {
+                let states = [
+                    MmppState {
+                        relative_rate: 1.0,
+                        mean_sojourn: *mean_on,
+                    },
+                    MmppState {
+                        relative_rate: 0.0,
+                        mean_sojourn: *mean_off,
+                    },
+                ];
+                Arrivals::Mmpp(Mmpp::new(&states, mean)?)
+            }
+            ArrivalProcess::Mmpp { states } => Arrivals::Mmpp(Mmpp::new(states, mean)?),
+        };
+        Ok(arrivals)
+    }
+}
+
+/// A sampler of inter-arrival times, created by [`ArrivalProcess::arrivals`].
+#[derive(Debug, Clone)]
+pub enum Arrivals {
+    Exp(Exp<f64>),
+    LogNormal(LogNormal<f64>),
+    Pareto(Pareto<f64>),
+    Weibull(Weibull<f64>),
+    Mmpp(Mmpp),
+}
+
+impl Arrivals {
+    /// The time until the next arrival, in nanoseconds.
+    pub fn next_delta(&mut self, mut rng: impl Rng) -> f64 {
+        match self {
+            Arrivals::Exp(dist) => dist.sample(&mut rng),
+            Arrivals::LogNormal(dist) => dist.sample(&mut rng),
+            Arrivals::Pareto(dist) => dist.sample(&mut rng),
+            Arrivals::Weibull(dist) => dist.sample(&mut rng),
+            Arrivals::Mmpp(mmpp) => mmpp.next_delta(&mut rng),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mmpp {
+    // Absolute arrival rates, per nanosecond
+    rates: Vec<f64>,
+    mean_sojourns: Vec<f64>,
+    sojourns: Vec<Exp<f64>>,
+    state: Option<usize>,
+    // Time left in the current state
+    remaining: f64,
+}
+
+impl Mmpp {
+    fn new(states: &[MmppState], mean_i: f64) -> Result<Self, Error> {
+        if states.len() < 2 {
+            return Err(Error::TooFewStates);
+        }
+        // The embedded jump chain is uniform over the other states, so in the long run each
+        // state is entered equally often and the time spent in it is proportional to its mean
+        // sojourn. Scale rates so that the long-run rate is `1 / mean_i`.
+        let total_time = states
+            .iter()
+            .map(|s| s.mean_sojourn.into_f64())
+            .sum::<f64>();
+        let relative_rate = states
+            .iter()
+            .map(|s| s.relative_rate * s.mean_sojourn.into_f64() / total_time)
+            .sum::<f64>();
+        if !(relative_rate > 0.0 && relative_rate.is_finite())
+            || states.iter().any(|s| s.relative_rate < 0.0)
+        {
+            return Err(Error::InvalidRates);
+        }
+        let scale = (mean_i * relative_rate).recip();
+        let rates = states.iter().map(|s| s.relative_rate * scale).collect();
+        let mean_sojourns = states
+            .iter()
+            .map(|s| s.mean_sojourn.into_f64())
+            .collect::<Vec<_>>();
+        let sojourns = mean_sojourns
+            .iter()
+            .map(|mean| Exp::new(mean.recip()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            rates,
+            mean_sojourns,
+            sojourns,
+            state: None,
+            remaining: 0.0,
+        })
+    }
+
+    fn next_delta(&mut self, mut rng: impl Rng) -> f64 {
+        // Start in a state chosen in proportion to the time spent in it
+        let mut state = match self.state {
+            Some(state) => state,
+            None => {
+                let state = self.start_state(&mut rng);
+                self.remaining = self.sojourns[state].sample(&mut rng);
+                state
+            }
+        };
+        // Arrivals within a state are memoryless, so a wait that outlasts the state can be
+        // abandoned and redrawn in the next one
+        let mut delta = 0.0;
+        loop {
+            let rate = self.rates[state];
+            let wait = match rate > 0.0 {
+                true => -(1.0 - rng.gen::<f64>()).ln() / rate,
+                false => f64::INFINITY,
+            };
+            if wait <= self.remaining {
+                self.remaining -= wait;
+                self.state = Some(state);
+                return delta + wait;
+            }
+            delta += self.remaining;
+            let next = rng.gen_range(0..self.rates.len() - 1);
+            state = if next >= state { next + 1 } else { next };
+            self.remaining = self.sojourns[state].sample(&mut rng);
+        }
+    }
+
+    fn start_state(&self, mut rng: impl Rng) -> usize {
+        let total = self.mean_sojourns.iter().sum::<f64>();
+        let mut x = rng.gen::<f64>() * total;
+        for (i, mean) in self.mean_sojourns.iter().enumerate() {
+            if x < *mean {
+                return i;
+            }
+            x -= mean;
+        }
+        self.mean_sojourns.len() - 1
+    }
+}
+
+/// The gamma function, by the Lanczos approximation.
+fn gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Reflection formula
+        return PI / ((PI * x).sin() * gamma(1.0 - x));
+    }
+    let x = x - 1.0;
+    let t = x + G + 0.5;
+    let sum = COEFFS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFS[0], |acc, (i, c)| acc + c / (x + (i + 1) as f64));
+    (2.0 * PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * sum
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("the Pareto shape must exceed 1 for the mean to be finite")]
+    InfiniteMean,
+
+    #[error("an MMPP needs at least two states")]
+    TooFewStates,
+
+    #[error("MMPP rates must be non-negative, and not all zero")]
+    InvalidRates,
+
+    #[error("invalid exponential distribution")]
+    Exp(#[from] rand_distr::ExpError),
+
+    #[error("invalid normal distribution")]
+    Normal(#[from] rand_distr::NormalError),
+
+    #[error("invalid Pareto distribution")]
+    Pareto(#[from] rand_distr::ParetoError),
+
+    #[error("invalid Weibull distribution")]
+    Weibull(#[from] rand_distr::WeibullError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gamma_correct() {
+        for (x, expected) in [
+            (1.0, 1.0),
+            (5.0, 24.0),
+            (0.5, PI.sqrt()),
+            (1.5, PI.sqrt() / 2.0),
+        ] {
+            assert!((gamma(x) - expected).abs() < 1e-10, "gamma({x})");
+        }
+    }
+
+    #[test]
+    fn processes_hit_target_mean() -> anyhow::Result<()> {
+        let mean_i = Nanosecs::new(10_000);
+        let processes = [
+            ArrivalProcess::Poisson,
+            ArrivalProcess::LogNormal { sigma: 2.0 },
+            ArrivalProcess::Pareto { shape: 3.0 },
+            ArrivalProcess::Weibull { shape: 0.5 },
+            ArrivalProcess::OnOff {
+                mean_on: Nanosecs::new(50_000),
+                mean_off: Nanosecs::new(150_000),
+            },
+            ArrivalProcess::Mmpp {
+                states: vec![
+                    MmppState {
+                        relative_rate: 10.0,
+                        mean_sojourn: Nanosecs::new(20_000),
+                    },
+                    MmppState {
+                        relative_rate: 1.0,
+                        mean_sojourn: Nanosecs::new(100_000),
+                    },
+                    MmppState {
+                        relative_rate: 0.0,
+                        mean_sojourn: Nanosecs::new(50_000),
+                    },
+                ],
+            },
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        for process in processes {
+            let mut arrivals = process.arrivals(mean_i)?;
+            let n = 500_000;
+            let mean = (0..n).map(|_| arrivals.next_delta(&mut rng)).sum::<f64>() / n as f64;
+            let err = (mean - mean_i.into_f64()).abs() / mean_i.into_f64();
+            assert!(err < 0.05, "{process:?}: mean {mean}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_processes_rejected() {
+        let mean_i = Nanosecs::new(10_000);
+        assert!(ArrivalProcess::Pareto { shape: 1.0 }
+            .arrivals(mean_i)
+            .is_err());
+        let all_off = ArrivalProcess::OnOff {
+            mean_on: Nanosecs::ZERO,
+            mean_off: Nanosecs::new(1),
+        };
+        assert!(all_off.arrivals(mean_i).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use parsimon::core::{
+    network::{Channel, Flow, FlowId, Network, NodeId},
+    routing::RoutingAlgo,
+    units::{Bytes, Nanosecs},
+};
+use rand::prelude::*;
+use rustc_hash::FxHashMap;
+
+use crate::{
+    fabric::{Cluster, FabricRoutes},
+    spatial::{CellTraffic, SpatialWorkload},
+};
+
+/// How a [`FlowGenerator`](super::FlowGenerator) finds the channel that limits the load.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Calibration {
+    /// Compute each channel's expected share of flows exactly, by pushing the traffic matrix
+    /// through the routes and splitting evenly among ECMP next hops. Fast and deterministic.
+    #[default]
+    Analytic,
+    /// Route sampled test flows through a parsimon `Network` and count them per channel. Much
+    /// slower and subject to sampling noise; kept as a cross-check.
+    Sampled,
+}
+
+// Routes in a fabric are a handful of hops long; this only guards against routing loops.
+const MAX_HOPS: usize = 16;
+
+/// The expected fraction of flows sampled from the whole trace that cross each channel, keyed
+/// by `(from, to)`. Every host is assumed to reach other racks through its own ToR.
+pub(crate) fn channel_fractions(
+    spatial_wk: &SpatialWorkload,
+    routes: &impl RoutingAlgo,
+) -> FxHashMap<(NodeId, NodeId), f64> {
+    route_cells(spatial_wk.cell_traffic(), routes)
+}
+
+/// Spreads the probability of each cell over the channels its traffic crosses.
+pub(crate) fn route_cells(
+    cells: impl IntoIterator<Item = CellTraffic>,
+    routes: &impl RoutingAlgo,
+) -> FxHashMap<(NodeId, NodeId), f64> {
+    let mut fractions: FxHashMap<(NodeId, NodeId), f64> = FxHashMap::default();
+    for cell in cells {
+        for &(host, p) in &cell.src {
+            *fractions.entry((host, cell.src_tor)).or_default() += cell.prob * p;
+        }
+        for &(host, p) in &cell.dst {
+            *fractions.entry((cell.dst_tor, host)).or_default() += cell.prob * p;
+        }
+        if cell.src_tor == cell.dst_tor {
+            continue;
+        }
+        // Push the cell's probability towards the destination ToR one hop at a time
+        let mut frontier = vec![(cell.src_tor, cell.prob)];
+        for _ in 0..MAX_HOPS {
+            let mut next: FxHashMap<NodeId, f64> = FxHashMap::default();
+            for (node, mass) in frontier {
+                let hops = routes
+                    .next_hops(node, cell.dst_tor)
+                    .expect("ToR outside of routing table");
+                let share = mass / hops.len() as f64;
+                for hop in hops {
+                    *fractions.entry((node, hop)).or_default() += share;
+                    if hop != cell.dst_tor {
+                        *next.entry(hop).or_default() += share;
+                    }
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            frontier = next.into_iter().collect();
+        }
+    }
+    fractions
+}
+
+/// Like [`channel_fractions`], but estimated by routing `nr_test_flows` sampled flows through a
+/// parsimon `Network`.
+pub(crate) fn sampled_channel_fractions(
+    spatial_wk: &SpatialWorkload,
+    cluster: &Cluster,
+    nr_test_flows: usize,
+    mut rng: impl Rng,
+) -> FxHashMap<(NodeId, NodeId), f64> {
+    let nodes = cluster.nodes().cloned().collect::<Vec<_>>();
+    let links = cluster.links().cloned().collect::<Vec<_>>();
+    let network = Network::new_with_routes(&nodes, &links, FabricRoutes::new(cluster))
+        .expect("invalid cluster specification");
+    let flows = (0..nr_test_flows)
+        .map(|i| {
+            let (src, dst) = spatial_wk.sample(&mut rng);
+            Flow {
+                id: FlowId::new(i),
+                src,
+                dst,
+                size: Bytes::default(),
+                start: Nanosecs::default(),
+            }
+        })
+        .collect::<Vec<_>>();
+    let network = network.into_simulations(flows);
+    network
+        .channels()
+        .map(|chan| {
+            let frac = chan.nr_flows() as f64 / nr_test_flows as f64;
+            ((chan.src(), chan.dst()), frac)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entry::Entry,
+        spatial::{SpatialData, SpatialOpts},
+        testing::TINY_CLUSTER,
+    };
+
+    fn entry(src: (&str, &str), dst: (&str, &str)) -> Entry {
+        Entry {
+            srcrack: src.0.to_string(),
+            srcpod: src.1.to_string(),
+            dstrack: dst.0.to_string(),
+            dstpod: dst.1.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn spatial_workload(cluster: &Cluster) -> anyhow::Result<SpatialWorkload> {
+        let (r0, r1, r2) = (("r0", "p0"), ("r1", "p0"), ("r2", "p1"));
+        // Pod p0 needs both racks to map onto the cluster
+        let entries = std::iter::repeat_with(|| entry(r0, r2))
+            .take(3)
+            .chain([entry(r1, r1)]);
+        let spatial = SpatialData::from_entries(entries, &SpatialOpts::default())?;
+        Ok(spatial.map_to(cluster, StdRng::seed_from_u64(0))?)
+    }
+
+    #[test]
+    fn fractions_follow_ecmp_splits() -> anyhow::Result<()> {
+        let cluster: Cluster = serde_json::from_str(TINY_CLUSTER)?;
+        let spatial_wk = spatial_workload(&cluster)?;
+        let routes = FabricRoutes::new(&cluster);
+        let fractions = channel_fractions(&spatial_wk, &routes);
+
+        // Every flow leaves a host for its ToR and enters a host from its ToR
+        let host2tor = fractions
+            .iter()
+            .filter(|&(&(a, _), _)| a.inner() < 8)
+            .map(|(_, f)| f)
+            .sum::<f64>();
+        let tor2host = fractions
+            .iter()
+            .filter(|&(&(_, b), _)| b.inner() < 8)
+            .map(|(_, f)| f)
+            .sum::<f64>();
+        assert!((host2tor - 1.0).abs() < 1e-9 && (tor2host - 1.0).abs() < 1e-9);
+
+        // Inter-pod traffic splits evenly over the two fabric uplinks of its ToR
+        let uplinks = fractions
+            .iter()
+            .filter(|&(&(a, b), _)| (8..12).contains(&a.inner()) && b.inner() >= 12)
+            .map(|(_, &f)| f)
+            .collect::<Vec<_>>();
+        assert_eq!(uplinks.len(), 2);
+        assert!(uplinks.iter().all(|f| (f - 0.375).abs() < 1e-9));
+        Ok(())
+    }
+
+    #[test]
+    fn analytic_matches_sampled() -> anyhow::Result<()> {
+        let cluster: Cluster = serde_json::from_str(TINY_CLUSTER)?;
+        let spatial_wk = spatial_workload(&cluster)?;
+        let analytic = channel_fractions(&spatial_wk, &FabricRoutes::new(&cluster));
+        let rng = StdRng::seed_from_u64(0);
+        let sampled = sampled_channel_fractions(&spatial_wk, &cluster, 100_000, rng);
+        for chan in analytic.keys().chain(sampled.keys()) {
+            let a = analytic.get(chan).copied().unwrap_or_default();
+            let s = sampled.get(chan).copied().unwrap_or_default();
+            assert!((a - s).abs() < 0.01, "{chan:?}: analytic {a}, sampled {s}");
+        }
+        Ok(())
+    }
+}
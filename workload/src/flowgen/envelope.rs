@@ -0,0 +1,268 @@
+use std::f64::consts::TAU;
+
+use parsimon::core::units::Nanosecs;
+
+use crate::spatial::TimeWindows;
+
+/// A multiplier on the calibrated arrival rate that varies over simulated time, measured from
+/// the start of generation. The load target holds where the factor is 1.
+///
+/// Arrivals are drawn in operational time and mapped to simulated time so that operational time
+/// passes at the rate of the factor. This keeps the arrival process intact, burstiness and all,
+/// while its rate follows the envelope.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LoadEnvelope {
+    /// Linear interpolation between `(time, factor)` points, holding the first and last factors
+    /// outside them.
+    Piecewise { points: Vec<(Nanosecs, f64)> },
+    /// A diurnal curve, `1 + amplitude * sin(2π * time / period + phase)`.
+    Sinusoidal {
+        period: Nanosecs,
+        amplitude: f64,
+        phase: f64,
+    },
+    /// A factor of 1 until the first `(time, factor)` step, then the factor of the latest step.
+    Steps { steps: Vec<(Nanosecs, f64)> },
+}
+
+// Sinusoids are integrated piecewise-linearly, with this many pieces per period
+const PIECES_PER_PERIOD: f64 = 256.0;
+
+impl LoadEnvelope {
+    /// Replays the relative load of a trace's time windows, each lasting `window_duration` of
+    /// simulated time. Gaps between windows carry no load. Factors are normalized to average 1
+    /// over the trace.
+    pub fn from_windows(windows: &TimeWindows, window_duration: Nanosecs) -> Result<Self, Error> {
+        if windows.width == 0 {
+            return Err(Error::ZeroWindowWidth);
+        }
+        let first = windows.windows.first().ok_or(Error::Empty)?.start;
+        let mut steps = Vec::new();
+        let mut last_slot = None;
+        for window in &windows.windows {
+            let slot = (window.start - first) / windows.width;
+            if let Some(last) = last_slot {
+                if slot > last + 1 {
+                    steps.push((last + 1, 0.0));
+                }
+            }
+            steps.push((slot, window.matrix.total() as f64));
+            last_slot = Some(slot);
+        }
+        let nr_slots = last_slot.unwrap() + 1;
+        let mean = steps.iter().map(|&(_, total)| total).sum::<f64>() / nr_slots as f64;
+        let steps = steps
+            .into_iter()
+            .map(|(slot, total)| {
+                let time = Nanosecs::new(slot * window_duration.into_u64());
+                (time, total / mean)
+            })
+            .collect();
+        let envelope = LoadEnvelope::Steps { steps };
+        envelope.validate()?;
+        Ok(envelope)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        let points = match self {
+            LoadEnvelope::Piecewise { points } => points,
+            LoadEnvelope::Steps { steps } => steps,
+            LoadEnvelope::Sinusoidal {
+                period, amplitude, ..
+            } => {
+                if *period == Nanosecs::ZERO || !(0.0..=1.0).contains(amplitude) {
+                    return Err(Error::InvalidSinusoid);
+                }
+                return Ok(());
+            }
+        };
+        let (_, last) = points.last().ok_or(Error::Empty)?;
+        if points.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return Err(Error::Unsorted);
+        }
+        if points
+            .iter()
+            .any(|&(_, factor)| !(factor >= 0.0 && factor.is_finite()))
+        {
+            return Err(Error::InvalidFactor);
+        }
+        if *last <= 0.0 {
+            return Err(Error::NoFinalLoad);
+        }
+        Ok(())
+    }
+
+    /// The factor at `time`.
+    pub fn factor(&self, time: Nanosecs) -> f64 {
+        let t = time.into_f64();
+        let (a, b, fa, fb) = self.piece(t);
+        match a.is_finite() && b.is_finite() {
+            true => fa + (fb - fa) * (t - a) / (b - a),
+            false => fa,
+        }
+    }
+
+    /// The simulated time at which `work` nanoseconds of operational time have passed since
+    /// simulated time `t`.
+    pub(crate) fn advance(&self, mut t: f64, mut work: f64) -> f64 {
+        loop {
+            if work <= 0.0 {
+                return t;
+            }
+            let (a, b, fa, fb) = self.piece(t);
+            let (ft, slope) = match a.is_finite() && b.is_finite() {
+                true => {
+                    let slope = (fb - fa) / (b - a);
+                    (fa + slope * (t - a), slope)
+                }
+                false => (fa, 0.0),
+            };
+            let area = match b.is_finite() {
+                true => (ft + ft + slope * (b - t)) / 2.0 * (b - t),
+                false => f64::INFINITY,
+            };
+            if area >= work {
+                // Solve `ft * d + slope * d^2 / 2 = work` for the smallest non-negative `d`
+                let disc = (ft * ft + 2.0 * slope * work).max(0.0);
+                return t + 2.0 * work / (ft + disc.sqrt());
+            }
+            work -= area;
+            t = b;
+        }
+    }
+
+    /// The linear piece `(start, end, start factor, end factor)` containing `t`. The last piece
+    /// is constant and ends at infinity.
+    fn piece(&self, t: f64) -> (f64, f64, f64, f64) {
+        match self {
+            LoadEnvelope::Piecewise { points } => {
+                let i = points.partition_point(|&(time, _)| time.into_f64() <= t);
+                match i {
+                    0 => (
+                        f64::NEG_INFINITY,
+                        points[0].0.into_f64(),
+                        points[0].1,
+                        points[0].1,
+                    ),
+                    i if i == points.len() => {
+                        let (_, last) = points[i - 1];
+                        (points[i - 1].0.into_f64(), f64::INFINITY, last, last)
+                    }
+                    i => {
+                        let ((a, fa), (b, fb)) = (points[i - 1], points[i]);
+                        (a.into_f64(), b.into_f64(), fa, fb)
+                    }
+                }
+            }
+            LoadEnvelope::Steps { steps } => {
+                let i = steps.partition_point(|&(time, _)| time.into_f64() <= t);
+                let (a, fa) = match i {
+                    0 => (f64::NEG_INFINITY, 1.0),
+                    i => (steps[i - 1].0.into_f64(), steps[i - 1].1),
+                };
+                let b = steps.get(i).map_or(f64::INFINITY, |s| s.0.into_f64());
+                (a, b, fa, fa)
+            }
+            LoadEnvelope::Sinusoidal {
+                period,
+                amplitude,
+                phase,
+            } => {
+                let width = period.into_f64() / PIECES_PER_PERIOD;
+                let a = (t / width).floor() * width;
+                let b = a + width;
+                let f = |x: f64| 1.0 + amplitude * (TAU * x / period.into_f64() + phase).sin();
+                (a, b, f(a), f(b))
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("the envelope has no points")]
+    Empty,
+
+    #[error("envelope points must be in increasing order of time")]
+    Unsorted,
+
+    #[error("envelope factors must be finite and non-negative")]
+    InvalidFactor,
+
+    #[error("the last envelope factor must be positive, or flows would stop arriving")]
+    NoFinalLoad,
+
+    #[error("a sinusoid needs a positive period and an amplitude between 0 and 1")]
+    InvalidSinusoid,
+
+    #[error("time windows must have a positive width")]
+    ZeroWindowWidth,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spatial::{TimeWindow, Tor2TorMatrix};
+
+    fn ns(t: u64) -> Nanosecs {
+        Nanosecs::new(t)
+    }
+
+    #[test]
+    fn advance_integrates_factor() {
+        let steps = LoadEnvelope::Steps {
+            steps: vec![(ns(100), 2.0), (ns(200), 0.0), (ns(300), 0.5)],
+        };
+        assert_eq!(steps.advance(0.0, 50.0), 50.0);
+        // 100 at factor 1, then 100 at factor 2
+        assert_eq!(steps.advance(0.0, 300.0), 200.0);
+        // Nothing passes between 200 and 300
+        assert_eq!(steps.advance(150.0, 110.0), 320.0);
+
+        let ramp = LoadEnvelope::Piecewise {
+            points: vec![(ns(0), 0.0), (ns(100), 2.0)],
+        };
+        assert!((ramp.factor(ns(50)) - 1.0).abs() < 1e-12);
+        // The area under the ramp up to 50 is 25
+        assert!((ramp.advance(0.0, 25.0) - 50.0).abs() < 1e-9);
+        assert!((ramp.advance(0.0, 300.0) - 200.0).abs() < 1e-9);
+
+        // A full period of a sinusoid averages 1
+        let sine = LoadEnvelope::Sinusoidal {
+            period: ns(1000),
+            amplitude: 0.9,
+            phase: 0.3,
+        };
+        assert!((sine.advance(0.0, 1000.0) - 1000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn windows_replayed() -> anyhow::Result<()> {
+        let matrix = |count| Tor2TorMatrix::from_entries(1, [(0, 0, count)], vec!["r0".into()]);
+        let windows = TimeWindows {
+            width: 10,
+            windows: vec![
+                TimeWindow {
+                    start: 20,
+                    matrix: matrix(1),
+                },
+                TimeWindow {
+                    start: 50,
+                    matrix: matrix(3),
+                },
+            ],
+        };
+        let envelope = LoadEnvelope::from_windows(&windows, ns(1000))?;
+        let expected = vec![(ns(0), 1.0), (ns(1000), 0.0), (ns(3000), 3.0)];
+        assert_eq!(envelope, LoadEnvelope::Steps { steps: expected });
+        assert!(LoadEnvelope::Steps { steps: vec![] }.validate().is_err());
+
+        let windows = TimeWindows {
+            width: 0,
+            ..windows
+        };
+        let result = LoadEnvelope::from_windows(&windows, ns(1000));
+        assert!(matches!(result, Err(Error::ZeroWindowWidth)));
+        Ok(())
+    }
+}
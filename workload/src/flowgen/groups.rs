@@ -0,0 +1,289 @@
+use parsimon::core::{
+    network::{Flow, FlowId, NodeId},
+    units::{Bytes, Nanosecs, Secs},
+};
+use rand::prelude::*;
+use rustc_hash::FxHashSet;
+use utils::Ecdf;
+
+use super::{arrivals, ArrivalProcess, FlowMeta, FlowMetaTable, StopWhen};
+use crate::{
+    fabric::Cluster,
+    spatial::{self, SpatialData, SpatialWorkload},
+};
+
+/// The shape of a synchronized group of flows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum GroupPattern {
+    /// Partition/aggregate: `fan_in` senders each send a response to one receiver.
+    Incast { fan_in: usize },
+    /// Shuffle coflow: every host of a set of `nr_hosts` sends to every other.
+    AllToAll { nr_hosts: usize },
+}
+
+/// Generates groups of flows that start together, such as incasts and shuffles.
+///
+/// Hosts are chosen through the spatial data's host pairs. An incast's receiver is the
+/// destination of a sampled pair, and its senders are the sources of further sampled pairs. A
+/// shuffle's hosts are the endpoints of sampled pairs. Hosts within a group are distinct; if too
+/// few distinct hosts turn up after a bounded number of samples, the group is smaller than asked.
+#[derive(Debug, typed_builder::TypedBuilder)]
+pub struct GroupGenerator {
+    spatial_data: SpatialData,
+    cluster: Cluster,
+    pattern: GroupPattern,
+    /// The size of each flow of a group, such as an incast response.
+    size_dist: Ecdf,
+    /// Groups started per second.
+    query_rate: f64,
+    /// How group inter-arrival times are distributed.
+    #[builder(default = ArrivalProcess::Poisson)]
+    arrivals: ArrivalProcess,
+    /// Each flow of a group starts up to this long after the group, uniformly at random.
+    #[builder(default = Nanosecs::ZERO)]
+    jitter: Nanosecs,
+    #[builder(default = Secs::ONE)]
+    start_time: Secs,
+    /// A flow count limit is checked between groups, so the last group is never cut short.
+    stop_when: StopWhen,
+    #[builder(default = FlowId::ZERO)]
+    id_start: FlowId,
+    #[builder(default = 0)]
+    seed: u64,
+}
+
+impl GroupGenerator {
+    pub fn generate(&self) -> Result<GroupedFlows, Error> {
+        if !self.query_rate.is_finite() || self.query_rate <= 0.0 {
+            return Err(Error::InvalidQueryRate(self.query_rate));
+        }
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let spatial_wk = self.spatial_data.map_to(&self.cluster, &mut rng)?;
+        let mean_i = Nanosecs::new((1e9 / self.query_rate).round() as u64);
+        let mut arrivals = self.arrivals.arrivals(mean_i)?;
+
+        let start_time: Nanosecs = self.start_time.into();
+        let (end, max_nr_flows) = match self.stop_when {
+            StopWhen::Elapsed(duration) => (start_time + duration.into(), usize::MAX),
+            StopWhen::NrFlows(max_nr_flows) => (Nanosecs::MAX, max_nr_flows),
+        };
+        let mut tagged = Vec::new();
+        let mut cur = start_time;
+        let mut group = 0;
+        while cur < end && tagged.len() < max_nr_flows {
+            for (src, dst) in self.pattern.pairs(&spatial_wk, &mut rng) {
+                let size = self.size_dist.sample(&mut rng);
+                let jitter = rng.gen_range(0..=self.jitter.into_u64());
+                let flow = Flow {
+                    id: FlowId::ZERO,
+                    src,
+                    dst,
+                    size: Bytes::new(size.round() as u64),
+                    start: cur + Nanosecs::new(jitter),
+                };
+                tagged.push((flow, group));
+            }
+            group += 1;
+            cur += Nanosecs::new(arrivals.next_delta(&mut rng).round() as u64);
+        }
+
+        // Jitter can reorder flows of neighbouring groups
+        tagged.sort_by_key(|(flow, _)| flow.start);
+        let (flows, groups) = tagged
+            .into_iter()
+            .enumerate()
+            .map(|(i, (flow, group))| {
+                let id = self.id_start + FlowId::new(i);
+                (Flow { id, ..flow }, group)
+            })
+            .unzip();
+        Ok(GroupedFlows { flows, groups })
+    }
+}
+
+impl GroupPattern {
+    /// The host pairs of one group.
+    fn pairs(&self, spatial_wk: &SpatialWorkload, mut rng: impl Rng) -> Vec<(NodeId, NodeId)> {
+        match *self {
+            GroupPattern::Incast { fan_in } => {
+                let (_, receiver) = spatial_wk.sample(&mut rng);
+                let senders = distinct_hosts(fan_in, &[receiver], &mut rng, |rng| {
+                    vec![spatial_wk.sample(rng).0]
+                });
+                senders.into_iter().map(|src| (src, receiver)).collect()
+            }
+            GroupPattern::AllToAll { nr_hosts } => {
+                let hosts = distinct_hosts(nr_hosts, &[], &mut rng, |rng| {
+                    let (src, dst) = spatial_wk.sample(rng);
+                    vec![src, dst]
+                });
+                hosts
+                    .iter()
+                    .flat_map(|&src| {
+                        hosts
+                            .iter()
+                            .filter(move |&&dst| dst != src)
+                            .map(move |&dst| (src, dst))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Collects up to `n` distinct hosts not in `exclude`, in the order `draw` produces them, giving
+/// up after a bounded number of draws.
+fn distinct_hosts<R: Rng>(
+    n: usize,
+    exclude: &[NodeId],
+    rng: &mut R,
+    mut draw: impl FnMut(&mut R) -> Vec<NodeId>,
+) -> Vec<NodeId> {
+    const DRAWS_PER_HOST: usize = 100;
+    let mut seen = exclude.iter().copied().collect::<FxHashSet<_>>();
+    let mut hosts = Vec::with_capacity(n);
+    for _ in 0..n * DRAWS_PER_HOST {
+        if hosts.len() == n {
+            break;
+        }
+        for host in draw(rng) {
+            if hosts.len() < n && seen.insert(host) {
+                hosts.push(host);
+            }
+        }
+    }
+    hosts
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct GroupedFlows {
+    /// Flows in order of start time, with IDs assigned in that order.
+    pub flows: Vec<Flow>,
+    /// The group ID of each flow in `flows`. Groups are numbered from zero in order of start.
+    pub groups: Vec<usize>,
+}
+
+impl GroupedFlows {
+    pub fn nr_groups(&self) -> usize {
+        self.groups.iter().max().map_or(0, |&g| g + 1)
+    }
+
+    /// The group of every flow.
+    pub fn meta(&self) -> FlowMetaTable {
+        self.flows
+            .iter()
+            .zip(&self.groups)
+            .map(|(flow, &group)| {
+                let meta = FlowMeta {
+                    group: Some(group),
+                    ..Default::default()
+                };
+                (flow.id, meta)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("the query rate must be positive and finite, but is {0}")]
+    InvalidQueryRate(f64),
+
+    #[error(transparent)]
+    Spatial(#[from] spatial::Error),
+
+    #[error(transparent)]
+    Arrivals(#[from] arrivals::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap;
+
+    use super::*;
+    use crate::{entry::Entry, spatial::SpatialOpts, testing::TINY_CLUSTER};
+
+    fn spatial() -> anyhow::Result<SpatialData> {
+        let racks = [("r0", "p0"), ("r1", "p0"), ("r2", "p1"), ("r3", "p1")];
+        let entries = racks.iter().flat_map(|&(src, srcpod)| {
+            racks.iter().map(move |&(dst, dstpod)| Entry {
+                srcrack: src.to_string(),
+                dstrack: dst.to_string(),
+                srcpod: srcpod.to_string(),
+                dstpod: dstpod.to_string(),
+                ..Default::default()
+            })
+        });
+        Ok(SpatialData::from_entries(entries, &SpatialOpts::default())?)
+    }
+
+    fn generator(pattern: GroupPattern, query_rate: f64) -> anyhow::Result<GroupGenerator> {
+        Ok(GroupGenerator::builder()
+            .spatial_data(spatial()?)
+            .cluster(serde_json::from_str(TINY_CLUSTER)?)
+            .pattern(pattern)
+            .size_dist(Ecdf::from_values(&[1000.0])?)
+            .query_rate(query_rate)
+            .jitter(Nanosecs::new(100))
+            .stop_when(StopWhen::NrFlows(600))
+            .build())
+    }
+
+    fn generate(pattern: GroupPattern) -> anyhow::Result<GroupedFlows> {
+        Ok(generator(pattern, 1000.0)?.generate()?)
+    }
+
+    fn by_group(grouped: &GroupedFlows) -> FxHashMap<usize, Vec<&Flow>> {
+        let mut group2flows: FxHashMap<_, Vec<_>> = FxHashMap::default();
+        for (flow, &group) in grouped.flows.iter().zip(&grouped.groups) {
+            group2flows.entry(group).or_default().push(flow);
+        }
+        group2flows
+    }
+
+    #[test]
+    fn incast_groups_share_receiver() -> anyhow::Result<()> {
+        let grouped = generate(GroupPattern::Incast { fan_in: 3 })?;
+        assert_eq!(grouped.flows.len(), 600);
+        assert_eq!(grouped.nr_groups(), 200);
+        assert!(grouped.flows.windows(2).all(|w| w[0].start <= w[1].start));
+        for flows in by_group(&grouped).values() {
+            assert_eq!(flows.len(), 3);
+            let receiver = flows[0].dst;
+            assert!(flows.iter().all(|f| f.dst == receiver && f.src != receiver));
+            let senders = flows.iter().map(|f| f.src).collect::<FxHashSet<_>>();
+            assert_eq!(senders.len(), 3);
+            let starts = flows.iter().map(|f| f.start.into_u64());
+            let spread = starts.clone().max().unwrap() - starts.min().unwrap();
+            assert!(spread <= 100);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shuffles_are_all_to_all() -> anyhow::Result<()> {
+        let grouped = generate(GroupPattern::AllToAll { nr_hosts: 4 })?;
+        assert_eq!(grouped.nr_groups(), 50);
+        for flows in by_group(&grouped).values() {
+            let pairs = flows
+                .iter()
+                .map(|f| (f.src, f.dst))
+                .collect::<FxHashSet<_>>();
+            assert_eq!(pairs.len(), 12);
+            assert!(pairs.iter().all(|(src, dst)| src != dst));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_query_rates_rejected() -> anyhow::Result<()> {
+        for query_rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let generator = generator(GroupPattern::Incast { fan_in: 3 }, query_rate)?;
+            assert!(matches!(
+                generator.generate(),
+                Err(Error::InvalidQueryRate(_))
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use parsimon::core::{
+    network::{types::Link, NodeId},
+    units::BitsPerSec,
+};
+use rustc_hash::FxHashMap;
+
+use crate::fabric::Cluster;
+
+/// The load a [`FlowGenerator`](super::FlowGenerator) scales its arrival rate to.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LoadTarget {
+    /// The most loaded channel reaches this load.
+    MaxChannel(f64),
+    /// The channels of `tier`, in both directions, reach this load on average.
+    TierAverage { tier: Tier, load: f64 },
+}
+
+impl LoadTarget {
+    pub fn load(&self) -> f64 {
+        match *self {
+            LoadTarget::MaxChannel(load) => load,
+            LoadTarget::TierAverage { load, .. } => load,
+        }
+    }
+
+    /// The same target with its load scaled by `factor`.
+    pub fn scale_by(self, factor: f64) -> Self {
+        match self {
+            LoadTarget::MaxChannel(load) => LoadTarget::MaxChannel(load * factor),
+            LoadTarget::TierAverage { tier, load } => LoadTarget::TierAverage {
+                tier,
+                load: load * factor,
+            },
+        }
+    }
+}
+
+impl From<f64> for LoadTarget {
+    fn from(max_load: f64) -> Self {
+        LoadTarget::MaxChannel(max_load)
+    }
+}
+
+/// A tier of links in the fabric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Tier {
+    HostTor,
+    TorFab,
+    FabSpine,
+}
+
+/// The per-channel utilization a generator predicts for the flows it generates.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct LoadProfile {
+    pub channels: Vec<ChannelLoad>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ChannelLoad {
+    pub src: NodeId,
+    pub dst: NodeId,
+    pub tier: Tier,
+    pub bandwidth: BitsPerSec,
+    /// The expected fraction of the channel's bandwidth in use.
+    pub load: f64,
+}
+
+impl LoadProfile {
+    /// The loads of the channels of `tier`, or of all channels.
+    pub fn loads(&self, tier: Option<Tier>) -> impl Iterator<Item = f64> + '_ {
+        self.channels
+            .iter()
+            .filter(move |c| tier.is_none() || tier == Some(c.tier))
+            .map(|c| c.load)
+    }
+
+    pub fn max(&self, tier: Option<Tier>) -> f64 {
+        self.loads(tier).fold(0.0, f64::max)
+    }
+
+    pub fn mean(&self, tier: Option<Tier>) -> f64 {
+        let (sum, n) = self
+            .loads(tier)
+            .fold((0.0, 0), |(sum, n), load| (sum + load, n + 1));
+        sum / n as f64
+    }
+}
+
+/// Finds the total arrival rate, in bits per second, that meets `target` given the expected
+/// fraction of flows crossing each channel, and the per-channel loads it leads to.
+pub(super) fn calibrate(
+    cluster: &Cluster,
+    fractions: &FxHashMap<(NodeId, NodeId), f64>,
+    target: LoadTarget,
+) -> Result<(BitsPerSec, LoadProfile), Error> {
+    // Before scaling, `load` is the utilization per bit per second of total rate
+    let mut profile = utilization(cluster, fractions);
+    let per_rate = match target {
+        LoadTarget::MaxChannel(_) => profile.max(None),
+        LoadTarget::TierAverage { tier, .. } => profile.mean(Some(tier)),
+    };
+    // A tier without channels has a NaN mean load
+    if per_rate.is_nan() || per_rate <= 0.0 {
+        return Err(Error::NoTrafficAtTarget);
+    }
+    let total_rate = target.load() / per_rate;
+    for chan in &mut profile.channels {
+        chan.load *= total_rate;
+    }
+    Ok((BitsPerSec::new(total_rate.round() as u64), profile))
+}
+
+/// The load of every channel of the cluster, given the bits per second crossing some of them.
+pub(super) fn utilization(
+    cluster: &Cluster,
+    rates: &FxHashMap<(NodeId, NodeId), f64>,
+) -> LoadProfile {
+    let channels = channels(cluster)
+        .map(|(src, dst, tier, bandwidth)| {
+            let rate = rates.get(&(src, dst)).copied().unwrap_or_default();
+            ChannelLoad {
+                src,
+                dst,
+                tier,
+                bandwidth,
+                load: rate / bandwidth.into_f64(),
+            }
+        })
+        .collect();
+    LoadProfile { channels }
+}
+
+/// Every channel of the cluster, as `(src, dst, tier, bandwidth)`.
+fn channels(cluster: &Cluster) -> impl Iterator<Item = (NodeId, NodeId, Tier, BitsPerSec)> + '_ {
+    let host2tor = cluster
+        .pods
+        .iter()
+        .flat_map(|p| p.racks.iter())
+        .flat_map(|r| r.host2tor.iter())
+        .map(|link| (link, Tier::HostTor));
+    let tor2fab = cluster
+        .pods
+        .iter()
+        .flat_map(|p| p.tor2fab.iter())
+        .map(|link| (link, Tier::TorFab));
+    let fab2spine = cluster.fab2spine.iter().map(|link| (link, Tier::FabSpine));
+    host2tor.chain(tor2fab).chain(fab2spine).flat_map(
+        |(
+            &Link {
+                a, b, bandwidth, ..
+            },
+            tier,
+        )| { [(a, b, tier, bandwidth), (b, a, tier, bandwidth)] },
+    )
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("no traffic crosses the channels of the load target")]
+    NoTrafficAtTarget,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TINY_CLUSTER;
+
+    #[test]
+    fn targets_scale_rate() -> anyhow::Result<()> {
+        let cluster: Cluster = serde_json::from_str(TINY_CLUSTER)?;
+        // All flows go from host 0 to host 2, through ToRs 8 and 9 and both fabric switches
+        let fractions = [
+            ((0, 8), 1.0),
+            ((8, 12), 0.5),
+            ((8, 13), 0.5),
+            ((12, 9), 0.5),
+            ((13, 9), 0.5),
+            ((9, 2), 1.0),
+        ]
+        .into_iter()
+        .map(|((a, b), frac)| ((NodeId::new(a), NodeId::new(b)), frac))
+        .collect();
+
+        // Host links are 10 Gbps and the busiest
+        let (rate, profile) = calibrate(&cluster, &fractions, LoadTarget::MaxChannel(0.5))?;
+        assert_eq!(rate, BitsPerSec::new(5_000_000_000));
+        assert!((profile.max(None) - 0.5).abs() < 1e-9);
+        assert!((profile.max(Some(Tier::TorFab)) - 0.5 * 5.0 / 40.0).abs() < 1e-9);
+        assert_eq!(profile.max(Some(Tier::FabSpine)), 0.0);
+
+        let target = LoadTarget::TierAverage {
+            tier: Tier::TorFab,
+            load: 0.1,
+        };
+        let (_, profile) = calibrate(&cluster, &fractions, target)?;
+        assert!((profile.mean(Some(Tier::TorFab)) - 0.1).abs() < 1e-9);
+
+        // Nothing crosses the spine, so no rate loads it
+        let target = LoadTarget::TierAverage {
+            tier: Tier::FabSpine,
+            load: 0.1,
+        };
+        let result = calibrate(&cluster, &fractions, target);
+        assert!(matches!(result, Err(Error::NoTrafficAtTarget)));
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use parsimon::core::network::FlowId;
+
+/// What a flow belongs to, beyond what [`Flow`](parsimon::core::network::Flow) records.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FlowMeta {
+    /// The name of the workload class the flow was generated for.
+    pub class: Option<String>,
+    /// The incast or all-to-all group the flow is part of.
+    pub group: Option<usize>,
+    pub priority: Option<u8>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Per-flow metadata kept alongside a list of flows, keyed by flow ID. Flows without an entry
+/// have no metadata.
+///
+/// Serializes as a list of rows, each a flow ID with the fields of its [`FlowMeta`].
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "Vec<MetaRow>", into = "Vec<MetaRow>")]
+pub struct FlowMetaTable {
+    metas: BTreeMap<FlowId, FlowMeta>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct MetaRow {
+    id: FlowId,
+    #[serde(flatten)]
+    meta: FlowMeta,
+}
+
+impl FlowMetaTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the metadata of flow `id`, returning what it replaces.
+    pub fn insert(&mut self, id: FlowId, meta: FlowMeta) -> Option<FlowMeta> {
+        self.metas.insert(id, meta)
+    }
+
+    pub fn get(&self, id: FlowId) -> Option<&FlowMeta> {
+        self.metas.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: FlowId) -> Option<&mut FlowMeta> {
+        self.metas.get_mut(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.metas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.metas.is_empty()
+    }
+
+    /// Entries in order of flow ID.
+    pub fn iter(&self) -> impl Iterator<Item = (FlowId, &FlowMeta)> {
+        self.metas.iter().map(|(&id, meta)| (id, meta))
+    }
+}
+
+impl FromIterator<(FlowId, FlowMeta)> for FlowMetaTable {
+    fn from_iter<T: IntoIterator<Item = (FlowId, FlowMeta)>>(iter: T) -> Self {
+        Self {
+            metas: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<(FlowId, FlowMeta)> for FlowMetaTable {
+    fn extend<T: IntoIterator<Item = (FlowId, FlowMeta)>>(&mut self, iter: T) {
+        self.metas.extend(iter)
+    }
+}
+
+impl From<Vec<MetaRow>> for FlowMetaTable {
+    fn from(rows: Vec<MetaRow>) -> Self {
+        rows.into_iter().map(|row| (row.id, row.meta)).collect()
+    }
+}
+
+impl From<FlowMetaTable> for Vec<MetaRow> {
+    fn from(table: FlowMetaTable) -> Self {
+        table
+            .metas
+            .into_iter()
+            .map(|(id, meta)| MetaRow { id, meta })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_round_trips_as_rows() -> anyhow::Result<()> {
+        let table = [
+            (
+                FlowId::new(3),
+                FlowMeta {
+                    class: Some("storage".into()),
+                    priority: Some(1),
+                    ..Default::default()
+                },
+            ),
+            (
+                FlowId::new(1),
+                FlowMeta {
+                    group: Some(0),
+                    tags: vec!["incast".into()],
+                    ..Default::default()
+                },
+            ),
+        ]
+        .into_iter()
+        .collect::<FlowMetaTable>();
+        let json = serde_json::to_value(&table)?;
+        assert_eq!(json[0]["id"], 1);
+        assert_eq!(json[1]["class"], "storage");
+        assert_eq!(serde_json::from_value::<FlowMetaTable>(json)?, table);
+        Ok(())
+    }
+}
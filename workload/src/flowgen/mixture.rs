@@ -0,0 +1,249 @@
+use parsimon::core::{
+    network::{Flow, FlowId},
+    units::{Nanosecs, Secs},
+};
+use rand::prelude::*;
+
+use super::{Error, FlowGenerator, FlowMeta, FlowMetaTable, LoadTarget, Run, StopWhen};
+
+/// One class of a [`Mixture`].
+#[derive(Debug)]
+pub struct WorkloadClass {
+    pub name: String,
+    /// Supplies the class's spatial data, size distributions and arrivals. The builder still
+    /// requires a load target, stop condition and seed, but these are overridden by the mixture,
+    /// as are the start time, flow IDs, warm-up and cool-down, so any placeholder will do.
+    pub generator: FlowGenerator,
+    /// The fraction of the mixture's load target this class is calibrated to. Classes with no
+    /// share produce no flows.
+    pub share: f64,
+    /// The priority recorded in the metadata of the class's flows.
+    pub priority: Option<u8>,
+}
+
+/// Several workload classes, generated independently and merged into one time-ordered list of
+/// flows tagged with their class.
+///
+/// Each class is calibrated on its own, to its share of the load target, and runs until the
+/// mixture's stop condition, whatever its generator was built with. With a target on the
+/// most loaded channel, classes may load different channels, so the merged workload's most loaded
+/// channel ends up somewhere between the largest share and the full target.
+#[derive(Debug, typed_builder::TypedBuilder)]
+pub struct Mixture {
+    classes: Vec<WorkloadClass>,
+    #[builder(setter(into))]
+    load_target: LoadTarget,
+    stop_when: StopWhen,
+    #[builder(default = Secs::ONE)]
+    start_time: Secs,
+    #[builder(default = FlowId::ZERO)]
+    id_start: FlowId,
+    #[builder(default = 0)]
+    seed: u64,
+}
+
+impl Mixture {
+    pub fn generate(&self) -> Result<MixedFlows, Error> {
+        // Each class gets its own seed, drawn in class order
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let per_class = self
+            .classes
+            .iter()
+            .map(|class| {
+                let run = Run {
+                    start_time: self.start_time,
+                    load_target: self.load_target.scale_by(class.share),
+                    stop_when: self.stop_when,
+                    warm_up: Nanosecs::ZERO,
+                    cool_down: Nanosecs::ZERO,
+                    id_start: FlowId::ZERO,
+                    seed: rng.gen(),
+                };
+                match class.share > 0.0 {
+                    true => Ok(class.generator.run(run)?.0.collect()),
+                    false => Ok(Vec::new()),
+                }
+            })
+            .collect::<Result<_, Error>>()?;
+        let (flows, tags) = merge(per_class, self.stop_when, self.id_start);
+        Ok(MixedFlows {
+            flows,
+            classes: self.classes.iter().map(|c| c.name.clone()).collect(),
+            priorities: self.classes.iter().map(|c| c.priority).collect(),
+            tags,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct MixedFlows {
+    /// Flows in order of start time, with IDs assigned in that order.
+    pub flows: Vec<Flow>,
+    /// Class names, in the order the classes were given to the mixture.
+    pub classes: Vec<String>,
+    /// The priority of each class, in the same order.
+    #[serde(default)]
+    pub priorities: Vec<Option<u8>>,
+    /// The index into `classes` of each flow in `flows`.
+    pub tags: Vec<usize>,
+}
+
+impl MixedFlows {
+    /// The class name of the `i`th flow.
+    pub fn class_of(&self, i: usize) -> &str {
+        &self.classes[self.tags[i]]
+    }
+
+    /// The class and priority of every flow.
+    pub fn meta(&self) -> FlowMetaTable {
+        self.flows
+            .iter()
+            .zip(&self.tags)
+            .map(|(flow, &class)| {
+                let meta = FlowMeta {
+                    class: Some(self.classes[class].clone()),
+                    priority: self.priorities.get(class).copied().flatten(),
+                    ..Default::default()
+                };
+                (flow.id, meta)
+            })
+            .collect()
+    }
+}
+
+/// Merges per-class flows by start time and renumbers them. With a flow count limit, each class
+/// was generated up to the limit on its own, so truncating the merged list keeps every class's
+/// share of the first flows intact.
+fn merge(
+    per_class: Vec<Vec<Flow>>,
+    stop_when: StopWhen,
+    id_start: FlowId,
+) -> (Vec<Flow>, Vec<usize>) {
+    let mut tagged = per_class
+        .into_iter()
+        .enumerate()
+        .flat_map(|(class, flows)| flows.into_iter().map(move |flow| (flow, class)))
+        .collect::<Vec<_>>();
+    // The sort is stable, so simultaneous flows stay in class order
+    tagged.sort_by_key(|(flow, _)| flow.start);
+    if let StopWhen::NrFlows(nr_flows) = stop_when {
+        tagged.truncate(nr_flows);
+    }
+    tagged
+        .into_iter()
+        .enumerate()
+        .map(|(i, (flow, class))| {
+            let id = id_start + FlowId::new(i);
+            (Flow { id, ..flow }, class)
+        })
+        .unzip()
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon::core::{network::NodeId, units::Bytes};
+    use utils::Ecdf;
+
+    use super::*;
+    use crate::{
+        entry::Entry,
+        spatial::{SpatialData, SpatialOpts},
+        testing::TINY_CLUSTER,
+    };
+
+    fn class(name: &str, share: f64, priority: u8) -> anyhow::Result<WorkloadClass> {
+        let racks = [("r0", "p0"), ("r1", "p0"), ("r2", "p1"), ("r3", "p1")];
+        let entries = racks.iter().flat_map(|&(src, srcpod)| {
+            racks.iter().map(move |&(dst, dstpod)| Entry {
+                srcrack: src.to_string(),
+                dstrack: dst.to_string(),
+                srcpod: srcpod.to_string(),
+                dstpod: dstpod.to_string(),
+                ..Default::default()
+            })
+        });
+        // The load target, stop condition and seed are placeholders
+        let generator = FlowGenerator::builder()
+            .spatial_data(SpatialData::from_entries(entries, &SpatialOpts::default())?)
+            .cluster(serde_json::from_str(TINY_CLUSTER)?)
+            .size_dist(Ecdf::from_values(&[1000.0, 10_000.0])?)
+            .lognorm_sigma(1.0)
+            .load_target(0.5)
+            .stop_when(StopWhen::NrFlows(1))
+            .build();
+        Ok(WorkloadClass {
+            name: name.to_string(),
+            generator,
+            share,
+            priority: Some(priority),
+        })
+    }
+
+    fn flows(starts: &[u64]) -> Vec<Flow> {
+        starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(start),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge_orders_and_tags() {
+        let per_class = vec![flows(&[10, 30, 50]), flows(&[20, 30, 40])];
+        let (merged, tags) = merge(per_class, StopWhen::NrFlows(5), FlowId::new(100));
+        let starts = merged
+            .iter()
+            .map(|f| f.start.into_u64())
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec![10, 20, 30, 30, 40]);
+        assert_eq!(tags, vec![0, 1, 0, 1, 1]);
+        let ids = merged.iter().map(|f| f.id.inner()).collect::<Vec<_>>();
+        assert_eq!(ids, vec![100, 101, 102, 103, 104]);
+    }
+
+    #[test]
+    fn classes_get_their_share_of_load() -> anyhow::Result<()> {
+        let classes = vec![
+            class("bulk", 0.75, 0)?,
+            class("idle", 0.0, 1)?,
+            class("query", 0.25, 2)?,
+        ];
+        let mixture = Mixture::builder()
+            .classes(classes)
+            .load_target(0.001)
+            .stop_when(StopWhen::Elapsed(Secs::new(1)))
+            .seed(7)
+            .build();
+        let mixed = mixture.generate()?;
+        assert_eq!(mixed.classes, vec!["bulk", "idle", "query"]);
+        assert_eq!(mixed.tags.len(), mixed.flows.len());
+        assert!(mixed.flows.windows(2).all(|w| w[0].start <= w[1].start));
+
+        // Every class runs for the mixture's second, not its generator's single flow
+        let mut bytes = [0.0; 3];
+        for (flow, &tag) in mixed.flows.iter().zip(&mixed.tags) {
+            bytes[tag] += flow.size.into_f64();
+        }
+        assert_eq!(bytes[1], 0.0);
+        let query_share = bytes[2] / (bytes[0] + bytes[2]);
+        assert!(
+            (query_share - 0.25).abs() < 0.03,
+            "query share {query_share}"
+        );
+
+        let table = mixed.meta();
+        assert_eq!(table.len(), mixed.flows.len());
+        for (i, flow) in mixed.flows.iter().enumerate() {
+            let meta = table.get(flow.id).unwrap();
+            assert_eq!(meta.class.as_deref(), Some(mixed.class_of(i)));
+            assert_eq!(meta.priority, Some(mixed.tags[i] as u8));
+        }
+        Ok(())
+    }
+}
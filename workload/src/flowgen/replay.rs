@@ -0,0 +1,285 @@
+use parsimon::core::{
+    network::{Flow, FlowId, NodeId},
+    units::{Bytes, Nanosecs, Secs},
+};
+use rand::prelude::*;
+use rustc_hash::FxHashMap;
+
+use super::{calibration, load, LoadTarget};
+use crate::{
+    fabric::{Cluster, FabricRoutes},
+    reconstruct::ReconstructedFlow,
+    spatial::{self, CellTraffic, SpatialData},
+};
+
+/// Replays flows reconstructed from a trace on a cluster, keeping their sizes and relative start
+/// times.
+///
+/// Racks are placed on the cluster as by [`SpatialData::map_to`]. Within a rack, trace hosts are
+/// assigned to cluster hosts in order of first appearance, cycling through the rack's hosts in a
+/// random order, so a trace host always maps to the same cluster host. Flows whose racks the
+/// spatial data lacks, or whose endpoints land on the same host, are skipped.
+///
+/// The spatial data's host skew is not used. Each trace host keeps its own flows, so the skew
+/// of the trace carries over as is, except where several trace hosts share a cluster host.
+#[derive(Debug, typed_builder::TypedBuilder)]
+pub struct TraceReplay {
+    spatial_data: SpatialData,
+    cluster: Cluster,
+    flows: Vec<ReconstructedFlow>,
+    /// Simulated nanoseconds per unit of trace timestamp. Must be positive and finite. Ignored if
+    /// `load_target` is set.
+    #[builder(default = 1.0)]
+    time_scale: f64,
+    /// Stretches or compresses time so that the replayed flows meet this load on average over
+    /// the trace.
+    #[builder(default, setter(strip_option))]
+    load_target: Option<LoadTarget>,
+    #[builder(default = Secs::ONE)]
+    start_time: Secs,
+    #[builder(default = FlowId::ZERO)]
+    id_start: FlowId,
+    #[builder(default = 0)]
+    seed: u64,
+    /// Indices of the cluster pods to place the spatial data's pods on. Defaults to all pods.
+    #[builder(default, setter(strip_option))]
+    pods: Option<Vec<usize>>,
+}
+
+/// A trace flow placed on the cluster.
+struct Placed {
+    src: (NodeId, NodeId),
+    dst: (NodeId, NodeId),
+    size: Bytes,
+    start: u64,
+}
+
+impl TraceReplay {
+    pub fn replay(&self) -> Result<Vec<Flow>, Error> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let all_pods = (0..self.cluster.pods.len()).collect::<Vec<_>>();
+        let pods = self.pods.as_ref().unwrap_or(&all_pods);
+        let name2tor = self
+            .spatial_data
+            .place_racks(&self.cluster, pods, &mut rng)?;
+        let mut tor2hosts = spatial::tor2hosts(&self.cluster);
+
+        // Assign trace hosts to cluster hosts, shuffling a rack's hosts when it is first seen
+        let mut assigned = FxHashMap::default();
+        let mut placed = Vec::new();
+        for flow in &self.flows {
+            let (Some(&src_tor), Some(&dst_tor)) = (
+                name2tor.get(flow.srcrack.as_str()),
+                name2tor.get(flow.dstrack.as_str()),
+            ) else {
+                continue;
+            };
+            let mut host_of = |tor, ip| {
+                assign_host(&mut assigned, &mut tor2hosts, tor, ip, &mut rng).map(|h| (tor, h))
+            };
+            let (Some(src), Some(dst)) = (
+                host_of(src_tor, &flow.tuple.srcip),
+                host_of(dst_tor, &flow.tuple.dstip),
+            ) else {
+                continue;
+            };
+            if src.1 != dst.1 {
+                placed.push(Placed {
+                    src,
+                    dst,
+                    size: Bytes::new((flow.size.round() as u64).max(1)),
+                    start: flow.start,
+                });
+            }
+        }
+        let first = placed.iter().map(|p| p.start).min().ok_or(Error::NoFlows)?;
+
+        let time_scale = match self.load_target {
+            Some(target) => self.time_scale_for(&placed, first, target)?,
+            None if self.time_scale > 0.0 && self.time_scale.is_finite() => self.time_scale,
+            None => return Err(Error::InvalidTimeScale(self.time_scale)),
+        };
+        let start_time: Nanosecs = self.start_time.into();
+        let mut flows = placed
+            .into_iter()
+            .map(|p| {
+                let offset = ((p.start - first) as f64 * time_scale).round() as u64;
+                Flow {
+                    id: FlowId::ZERO,
+                    src: p.src.1,
+                    dst: p.dst.1,
+                    size: p.size,
+                    start: start_time + Nanosecs::new(offset),
+                }
+            })
+            .collect::<Vec<_>>();
+        flows.sort_by_key(|f| f.start);
+        for (i, flow) in flows.iter_mut().enumerate() {
+            flow.id = self.id_start + FlowId::new(i);
+        }
+        Ok(flows)
+    }
+
+    /// The time scale at which the placed flows' bytes meet `target`.
+    fn time_scale_for(
+        &self,
+        placed: &[Placed],
+        first: u64,
+        target: LoadTarget,
+    ) -> Result<f64, Error> {
+        let last = placed.iter().map(|p| p.start).max().unwrap();
+        if last == first {
+            return Err(Error::NoDuration);
+        }
+        let mut pair2bytes: FxHashMap<_, f64> = FxHashMap::default();
+        for p in placed {
+            *pair2bytes.entry((p.src, p.dst)).or_default() += p.size.into_f64();
+        }
+        let total_bytes = pair2bytes.values().sum::<f64>();
+        let cells = pair2bytes
+            .into_iter()
+            .map(|((src, dst), bytes)| CellTraffic {
+                prob: bytes / total_bytes,
+                src_tor: src.0,
+                dst_tor: dst.0,
+                src: vec![(src.1, 1.0)],
+                dst: vec![(dst.1, 1.0)],
+            });
+        let fractions = calibration::route_cells(cells, &FabricRoutes::new(&self.cluster));
+        let (rate, _) = load::calibrate(&self.cluster, &fractions, target)?;
+        let duration = total_bytes * 8.0 / rate.into_f64() * 1e9;
+        Ok(duration / (last - first) as f64)
+    }
+}
+
+/// The cluster host trace host `ip` of the rack on `tor` maps to.
+fn assign_host<'a>(
+    assigned: &mut FxHashMap<NodeId, FxHashMap<&'a str, NodeId>>,
+    tor2hosts: &mut FxHashMap<NodeId, Vec<NodeId>>,
+    tor: NodeId,
+    ip: &'a str,
+    rng: &mut StdRng,
+) -> Option<NodeId> {
+    let hosts = tor2hosts.get_mut(&tor).filter(|hosts| !hosts.is_empty())?;
+    let rack_hosts = assigned.entry(tor).or_insert_with(|| {
+        hosts.shuffle(rng);
+        FxHashMap::default()
+    });
+    let next = hosts[rack_hosts.len() % hosts.len()];
+    Some(*rack_hosts.entry(ip).or_insert(next))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("no trace flow could be placed on the cluster")]
+    NoFlows,
+
+    #[error("all placed flows start at the same time, so no time scale meets the load target")]
+    NoDuration,
+
+    #[error("the time scale must be positive and finite, but is {0}")]
+    InvalidTimeScale(f64),
+
+    #[error(transparent)]
+    Spatial(#[from] spatial::Error),
+
+    #[error(transparent)]
+    Load(#[from] load::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entry::Entry, reconstruct::FiveTuple, spatial::SpatialOpts, testing::TINY_CLUSTER,
+    };
+
+    fn trace_flow(srcip: &str, dstip: &str, dstrack: &str, start: u64) -> ReconstructedFlow {
+        ReconstructedFlow {
+            tuple: FiveTuple {
+                srcip: srcip.to_string(),
+                dstip: dstip.to_string(),
+                srcport: "1000".to_string(),
+                dstport: "80".to_string(),
+                ipprotocol: "6".to_string(),
+            },
+            srcrack: "r0".to_string(),
+            dstrack: dstrack.to_string(),
+            start,
+            end: start,
+            nr_packets: 1,
+            sampled_bytes: 1000,
+            size: 1000.0,
+        }
+    }
+
+    fn replay(load_target: Option<LoadTarget>) -> anyhow::Result<Vec<Flow>> {
+        replay_scaled(100.0, load_target)
+    }
+
+    fn replay_scaled(
+        time_scale: f64,
+        load_target: Option<LoadTarget>,
+    ) -> anyhow::Result<Vec<Flow>> {
+        let racks = [("r0", "p0"), ("r1", "p0"), ("r2", "p1"), ("r3", "p1")];
+        let entries = racks.iter().map(|&(rack, pod)| Entry {
+            srcrack: rack.to_string(),
+            dstrack: rack.to_string(),
+            srcpod: pod.to_string(),
+            dstpod: pod.to_string(),
+            ..Default::default()
+        });
+        let flows = vec![
+            trace_flow("a", "x", "r2", 10),
+            trace_flow("a", "y", "r2", 30),
+            trace_flow("a", "x", "r2", 20),
+            // Not in the spatial data
+            trace_flow("a", "x", "r9", 15),
+            trace_flow("a", "z", "r2", 50),
+        ];
+        let replay = TraceReplay::builder()
+            .spatial_data(SpatialData::from_entries(entries, &SpatialOpts::default())?)
+            .cluster(serde_json::from_str(TINY_CLUSTER)?)
+            .flows(flows)
+            .time_scale(time_scale)
+            .build();
+        let replay = TraceReplay {
+            load_target,
+            ..replay
+        };
+        Ok(replay.replay()?)
+    }
+
+    #[test]
+    fn hosts_and_timing_kept() -> anyhow::Result<()> {
+        let flows = replay(None)?;
+        let starts = flows.iter().map(|f| f.start.into_u64()).collect::<Vec<_>>();
+        let offset = Nanosecs::from(Secs::ONE).into_u64();
+        assert_eq!(starts, [0, 1000, 2000, 4000].map(|t| offset + t));
+        // Trace host `a` is always the same cluster host, as is `x`
+        assert!(flows.iter().all(|f| f.src == flows[0].src));
+        assert_eq!(flows[0].dst, flows[1].dst);
+        // With two hosts per rack, the third trace host in r2 shares a host with the first
+        assert_ne!(flows[0].dst, flows[2].dst);
+        assert_eq!(flows[0].dst, flows[3].dst);
+
+        for time_scale in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let err = replay_scaled(time_scale, None).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref(),
+                Some(Error::InvalidTimeScale(_))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn time_scaled_to_load() -> anyhow::Result<()> {
+        // All 4000 bytes leave one 10 Gbps host link, which is the most loaded
+        let flows = replay(Some(LoadTarget::MaxChannel(0.5)))?;
+        let span = (flows[3].start - flows[0].start).into_f64();
+        let load = 4000.0 * 8.0 / span * 1e9 / 1e10;
+        assert!((load - 0.5).abs() < 1e-3, "load {load}");
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use parsimon::core::{
+    network::{Flow, NodeId},
+    units::Nanosecs,
+};
+use rustc_hash::FxHashMap;
+use utils::Ecdf;
+
+use super::{calibration, load, LoadProfile, Tier};
+use crate::{
+    fabric::{Cluster, FabricRoutes},
+    spatial::{CellTraffic, Locality},
+};
+
+/// What a set of flows was meant to look like. Targets left unset are not compared against.
+#[derive(Debug, Clone, Default)]
+pub struct ReportTargets {
+    pub size_dist: Option<Ecdf>,
+    pub mean_inter_arrival: Option<Nanosecs>,
+    /// The per-channel loads the flows were calibrated to produce.
+    pub profile: Option<LoadProfile>,
+}
+
+/// A summary of a set of flows, compared against the targets they were generated for.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WorkloadReport {
+    pub nr_flows: usize,
+    /// The time from the first flow's start to the last's.
+    pub duration: Nanosecs,
+    /// The mean flow size, in bytes.
+    pub mean_size: f64,
+    pub target_mean_size: Option<f64>,
+    /// The Kolmogorov-Smirnov distance between the flow sizes and the target size distribution.
+    pub size_ks_distance: Option<f64>,
+    /// The mean time between consecutive flow starts, in nanoseconds.
+    pub mean_inter_arrival: f64,
+    pub target_mean_inter_arrival: Option<f64>,
+    pub localities: LocalityFractions,
+    pub tiers: Vec<TierLoad>,
+    /// The load each channel carries if the flows' bytes are spread evenly over the duration
+    /// and split evenly over ECMP paths.
+    pub offered: LoadProfile,
+}
+
+/// The fraction of flows of each locality.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+pub struct LocalityFractions {
+    pub intra_rack: f64,
+    pub intra_pod: f64,
+    pub inter_pod: f64,
+}
+
+/// Offered and predicted loads of the channels of a tier.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct TierLoad {
+    pub tier: Tier,
+    pub mean: f64,
+    pub max: f64,
+    pub predicted_mean: Option<f64>,
+    pub predicted_max: Option<f64>,
+}
+
+/// How far a report may stray from its targets before [`WorkloadReport::issues`] flags it.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct Tolerances {
+    pub max_ks_distance: f64,
+    /// The largest allowed relative error of means and tier loads.
+    pub max_rel_err: f64,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Self {
+            max_ks_distance: 0.05,
+            max_rel_err: 0.1,
+        }
+    }
+}
+
+impl WorkloadReport {
+    pub fn new(flows: &[Flow], cluster: &Cluster, targets: &ReportTargets) -> Result<Self, Error> {
+        let first = flows.iter().map(|f| f.start).min().ok_or(Error::NoFlows)?;
+        let last = flows.iter().map(|f| f.start).max().unwrap();
+        let duration = last - first;
+        if duration == Nanosecs::ZERO {
+            return Err(Error::NoDuration);
+        }
+        let nr_flows = flows.len();
+
+        let sizes = flows.iter().map(|f| f.size.into_f64()).collect::<Vec<_>>();
+        let mean_size = sizes.iter().sum::<f64>() / nr_flows as f64;
+        let mean_inter_arrival = duration.into_f64() / (nr_flows - 1) as f64;
+
+        // Classify flows and total the bits each host pair sends
+        let host2loc = host_locations(cluster);
+        let locate = |host| host2loc.get(&host).copied().ok_or(Error::UnknownHost(host));
+        let mut counts = LocalityFractions::default();
+        let mut pair2bits: FxHashMap<_, f64> = FxHashMap::default();
+        for flow in flows {
+            let (src_tor, src_pod) = locate(flow.src)?;
+            let (dst_tor, dst_pod) = locate(flow.dst)?;
+            match Locality::classify(src_tor == dst_tor, src_pod == dst_pod) {
+                Locality::IntraRack => counts.intra_rack += 1.0,
+                Locality::IntraPod => counts.intra_pod += 1.0,
+                Locality::InterPod => counts.inter_pod += 1.0,
+            }
+            *pair2bits
+                .entry(((src_tor, flow.src), (dst_tor, flow.dst)))
+                .or_default() += flow.size.into_f64() * 8.0;
+        }
+        let localities = LocalityFractions {
+            intra_rack: counts.intra_rack / nr_flows as f64,
+            intra_pod: counts.intra_pod / nr_flows as f64,
+            inter_pod: counts.inter_pod / nr_flows as f64,
+        };
+
+        let secs = duration.into_f64() / 1e9;
+        let cells = pair2bits.into_iter().map(|((src, dst), bits)| CellTraffic {
+            prob: bits / secs,
+            src_tor: src.0,
+            dst_tor: dst.0,
+            src: vec![(src.1, 1.0)],
+            dst: vec![(dst.1, 1.0)],
+        });
+        let rates = calibration::route_cells(cells, &FabricRoutes::new(cluster));
+        let offered = load::utilization(cluster, &rates);
+        let tiers = [Tier::HostTor, Tier::TorFab, Tier::FabSpine]
+            .into_iter()
+            .map(|tier| TierLoad {
+                tier,
+                mean: offered.mean(Some(tier)),
+                max: offered.max(Some(tier)),
+                predicted_mean: targets.profile.as_ref().map(|p| p.mean(Some(tier))),
+                predicted_max: targets.profile.as_ref().map(|p| p.max(Some(tier))),
+            })
+            .collect();
+
+        Ok(Self {
+            nr_flows,
+            duration,
+            mean_size,
+            target_mean_size: targets.size_dist.as_ref().map(Ecdf::mean),
+            size_ks_distance: targets.size_dist.as_ref().map(|d| d.ks_distance(&sizes)),
+            mean_inter_arrival,
+            target_mean_inter_arrival: targets.mean_inter_arrival.map(Nanosecs::into_f64),
+            localities,
+            tiers,
+            offered,
+        })
+    }
+
+    /// Descriptions of where the flows stray from their targets by more than `tolerances`
+    /// allow.
+    pub fn issues(&self, tolerances: &Tolerances) -> Vec<String> {
+        let mut issues = Vec::new();
+        if let Some(ks) = self.size_ks_distance {
+            if ks > tolerances.max_ks_distance {
+                issues.push(format!(
+                    "flow sizes are {ks:.3} in KS distance from the target"
+                ));
+            }
+        }
+        let mut compare = |what: &str, realized: f64, target: Option<f64>| {
+            let Some(target) = target else {
+                return;
+            };
+            if (realized - target).abs() > tolerances.max_rel_err * target.abs() {
+                issues.push(format!(
+                    "{what} is {realized:.4}, but the target is {target:.4}"
+                ));
+            }
+        };
+        compare("mean flow size", self.mean_size, self.target_mean_size);
+        compare(
+            "mean inter-arrival time",
+            self.mean_inter_arrival,
+            self.target_mean_inter_arrival,
+        );
+        for tier in &self.tiers {
+            compare(
+                &format!("mean {:?} load", tier.tier),
+                tier.mean,
+                tier.predicted_mean,
+            );
+            compare(
+                &format!("max {:?} load", tier.tier),
+                tier.max,
+                tier.predicted_max,
+            );
+        }
+        issues
+    }
+}
+
+/// The ToR and pod index of every host of the cluster.
+fn host_locations(cluster: &Cluster) -> FxHashMap<NodeId, (NodeId, usize)> {
+    cluster
+        .pods
+        .iter()
+        .enumerate()
+        .flat_map(|(i, pod)| {
+            pod.racks.iter().flat_map(move |rack| {
+                rack.hosts
+                    .iter()
+                    .map(move |host| (host.id, (rack.tor.id, i)))
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("there are no flows to report on")]
+    NoFlows,
+
+    #[error("flow endpoint {0:?} is not a host of the cluster")]
+    UnknownHost(NodeId),
+
+    #[error("all flows start at the same time, so their load is undefined")]
+    NoDuration,
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon::core::{network::FlowId, units::Bytes};
+
+    use super::*;
+    use crate::testing::TINY_CLUSTER;
+
+    #[test]
+    fn offered_load_and_localities() -> anyhow::Result<()> {
+        let cluster: Cluster = serde_json::from_str(TINY_CLUSTER)?;
+        // Hosts 0 and 1 share a rack; host 2 is in another rack of the same pod
+        let flow = |i: usize, dst, start| Flow {
+            id: FlowId::new(i),
+            src: NodeId::new(0),
+            dst: NodeId::new(dst),
+            size: Bytes::new(625_000),
+            start: Nanosecs::new(start),
+        };
+        let flows = [flow(0, 1, 0), flow(1, 2, 500_000), flow(2, 2, 1_000_000)];
+        let targets = ReportTargets {
+            size_dist: Some(Ecdf::from_values(&[625_000.0])?),
+            mean_inter_arrival: Some(Nanosecs::new(400_000)),
+            profile: None,
+        };
+        let report = WorkloadReport::new(&flows, &cluster, &targets)?;
+        assert_eq!(report.duration, Nanosecs::new(1_000_000));
+        assert_eq!(report.size_ks_distance, Some(0.0));
+        assert!((report.localities.intra_rack - 1.0 / 3.0).abs() < 1e-9);
+        assert!((report.localities.intra_pod - 2.0 / 3.0).abs() < 1e-9);
+        // 15 Mb leave host 0 over 1 ms on a 10 Gbps link
+        assert!((report.offered.max(Some(Tier::HostTor)) - 1.5).abs() < 1e-9);
+
+        let issues = report.issues(&Tolerances::default());
+        assert!(issues
+            .iter()
+            .any(|i| i.starts_with("mean inter-arrival time")));
+        assert!(!issues.iter().any(|i| i.contains("KS distance")));
+
+        let result = WorkloadReport::new(&flows[..1], &cluster, &targets);
+        assert!(matches!(result, Err(Error::NoDuration)));
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+//! Reading and writing flows as JSON, CSV, or a compact binary format, and their metadata as
+//! JSON.
+//!
+//! A binary flow file starts with [`MAGIC`], a little-endian `u32` format version, and a
+//! little-endian `u32` length, at most [`MAX_HEADER_LEN`], followed by that many bytes of JSON
+//! [`FlowHeader`]. Then come the
+//! flows as fixed-width records of five little-endian `u64`s: id, source, destination, size in
+//! bytes, and start time in nanoseconds.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use parsimon::core::{
+    network::{Flow, FlowId, NodeId},
+    units::{Bytes, Nanosecs},
+};
+
+use crate::flowgen::FlowMetaTable;
+
+/// Identifies a binary flow file.
+pub const MAGIC: [u8; 8] = *b"WKLDFLOW";
+
+/// The binary format version this module reads and writes.
+pub const VERSION: u32 = 1;
+
+/// The longest header, in bytes, a binary flow file may have.
+pub const MAX_HEADER_LEN: u32 = 1 << 20;
+
+const RECORD_LEN: usize = 40;
+
+/// Writes flows one at a time as a JSON array, the format `parsimon::utils::read_flows` reads,
+/// without holding them all in memory.
+#[derive(Debug)]
+pub struct JsonFlowWriter<W: Write> {
+    inner: W,
+    nr_written: usize,
+}
+
+impl<W: Write> JsonFlowWriter<W> {
+    pub fn new(mut inner: W) -> Result<Self, Error> {
+        inner.write_all(b"[")?;
+        Ok(Self {
+            inner,
+            nr_written: 0,
+        })
+    }
+
+    pub fn write(&mut self, flow: &Flow) -> Result<(), Error> {
+        if self.nr_written > 0 {
+            self.inner.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut self.inner, flow)?;
+        self.nr_written += 1;
+        Ok(())
+    }
+
+    pub fn nr_written(&self) -> usize {
+        self.nr_written
+    }
+
+    /// Closes the array and flushes the writer. Without this, the output is not valid JSON.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.inner.write_all(b"]")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Streams `flows` to a JSON file at `path`, returning how many were written.
+pub fn write_flows_json(
+    path: impl AsRef<Path>,
+    flows: impl IntoIterator<Item = Flow>,
+) -> Result<usize, Error> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut writer = JsonFlowWriter::new(file)?;
+    for flow in flows {
+        writer.write(&flow)?;
+    }
+    let nr_written = writer.nr_written();
+    writer.finish()?;
+    Ok(nr_written)
+}
+
+/// Reads a JSON array of flows, as written by [`write_flows_json`].
+pub fn read_flows_json(path: impl AsRef<Path>) -> Result<Vec<Flow>, Error> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(file)?)
+}
+
+/// Writes `flows` as CSV with an `id,src,dst,size,start` header row, returning how many were
+/// written.
+pub fn write_flows_csv(
+    path: impl AsRef<Path>,
+    flows: impl IntoIterator<Item = Flow>,
+) -> Result<usize, Error> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    let mut nr_written = 0;
+    for flow in flows {
+        wtr.serialize(flow)?;
+        nr_written += 1;
+    }
+    wtr.flush()?;
+    Ok(nr_written)
+}
+
+pub fn read_flows_csv(path: impl AsRef<Path>) -> Result<Vec<Flow>, Error> {
+    let mut rdr = csv::Reader::from_path(path)?;
+    let flows = rdr.deserialize().collect::<Result<_, _>>()?;
+    Ok(flows)
+}
+
+/// Describes how the flows in a binary flow file were made.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FlowHeader {
+    /// The seed the flows were generated with, if any.
+    pub seed: Option<u64>,
+    /// The generator's parameters, in whatever form the writer chose.
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+/// Writes flows one at a time in the binary flow format.
+#[derive(Debug)]
+pub struct BinaryFlowWriter<W: Write> {
+    inner: W,
+    nr_written: usize,
+}
+
+impl<W: Write> BinaryFlowWriter<W> {
+    pub fn new(mut inner: W, header: &FlowHeader) -> Result<Self, Error> {
+        let header = serde_json::to_vec(header)?;
+        let header_len = u32::try_from(header.len())
+            .ok()
+            .filter(|&len| len <= MAX_HEADER_LEN)
+            .ok_or(Error::HeaderTooLong)?;
+        inner.write_all(&MAGIC)?;
+        inner.write_all(&VERSION.to_le_bytes())?;
+        inner.write_all(&header_len.to_le_bytes())?;
+        inner.write_all(&header)?;
+        Ok(Self {
+            inner,
+            nr_written: 0,
+        })
+    }
+
+    pub fn write(&mut self, flow: &Flow) -> Result<(), Error> {
+        let fields = [
+            flow.id.inner() as u64,
+            flow.src.inner() as u64,
+            flow.dst.inner() as u64,
+            flow.size.into_u64(),
+            flow.start.into_u64(),
+        ];
+        let mut record = [0; RECORD_LEN];
+        for (chunk, field) in record.chunks_exact_mut(8).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        self.inner.write_all(&record)?;
+        self.nr_written += 1;
+        Ok(())
+    }
+
+    pub fn nr_written(&self) -> usize {
+        self.nr_written
+    }
+
+    /// Flushes the writer and returns it.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reads flows one at a time from the binary flow format.
+#[derive(Debug)]
+pub struct BinaryFlowReader<R: Read> {
+    inner: R,
+    header: FlowHeader,
+}
+
+impl<R: Read> BinaryFlowReader<R> {
+    /// Reads and checks the file's preamble and header.
+    pub fn new(mut inner: R) -> Result<Self, Error> {
+        let mut magic = [0; 8];
+        inner.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::NotBinaryFlows);
+        }
+        let version = read_u32(&mut inner)?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let header_len = read_u32(&mut inner)?;
+        if header_len > MAX_HEADER_LEN {
+            return Err(Error::HeaderTooLong);
+        }
+        let mut header = vec![0; header_len as usize];
+        inner.read_exact(&mut header)?;
+        let header = serde_json::from_slice(&header)?;
+        Ok(Self { inner, header })
+    }
+
+    pub fn header(&self) -> &FlowHeader {
+        &self.header
+    }
+
+    fn read_flow(&mut self) -> Result<Option<Flow>, Error> {
+        let mut record = [0; RECORD_LEN];
+        let mut filled = 0;
+        while filled < RECORD_LEN {
+            match self.inner.read(&mut record[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(Error::TruncatedRecord),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let field = |i: usize| u64::from_le_bytes(record[i * 8..(i + 1) * 8].try_into().unwrap());
+        Ok(Some(Flow {
+            id: FlowId::new(field(0) as usize),
+            src: NodeId::new(field(1) as usize),
+            dst: NodeId::new(field(2) as usize),
+            size: Bytes::new(field(3)),
+            start: Nanosecs::new(field(4)),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for BinaryFlowReader<R> {
+    type Item = Result<Flow, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_flow().transpose()
+    }
+}
+
+fn read_u32(mut rdr: impl Read) -> Result<u32, Error> {
+    let mut bytes = [0; 4];
+    rdr.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Streams `flows` to a binary flow file at `path`, returning how many were written.
+pub fn write_flows_binary(
+    path: impl AsRef<Path>,
+    header: &FlowHeader,
+    flows: impl IntoIterator<Item = Flow>,
+) -> Result<usize, Error> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut writer = BinaryFlowWriter::new(file, header)?;
+    for flow in flows {
+        writer.write(&flow)?;
+    }
+    let nr_written = writer.nr_written();
+    writer.finish()?;
+    Ok(nr_written)
+}
+
+pub fn read_flows_binary(path: impl AsRef<Path>) -> Result<(FlowHeader, Vec<Flow>), Error> {
+    let reader = BinaryFlowReader::new(BufReader::new(File::open(path)?))?;
+    let header = reader.header().clone();
+    let flows = reader.collect::<Result<_, _>>()?;
+    Ok((header, flows))
+}
+
+/// A form flows can be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FlowFormat {
+    Json,
+    Csv,
+    Binary,
+}
+
+impl FlowFormat {
+    /// The format implied by the extension of `path`: `.json`, `.csv`, or `.bin`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(FlowFormat::Json),
+            Some("csv") => Ok(FlowFormat::Csv),
+            Some("bin") => Ok(FlowFormat::Binary),
+            _ => Err(Error::UnknownFormat(path.display().to_string())),
+        }
+    }
+}
+
+/// Reads flows from `path` in the format its extension implies. Only binary files have a
+/// header.
+pub fn read_flows(path: impl AsRef<Path>) -> Result<(Option<FlowHeader>, Vec<Flow>), Error> {
+    match FlowFormat::from_path(&path)? {
+        FlowFormat::Json => Ok((None, read_flows_json(path)?)),
+        FlowFormat::Csv => Ok((None, read_flows_csv(path)?)),
+        FlowFormat::Binary => {
+            let (header, flows) = read_flows_binary(path)?;
+            Ok((Some(header), flows))
+        }
+    }
+}
+
+/// Writes flows to `path` in the format its extension implies, returning how many were written.
+/// The header is only kept by the binary format.
+pub fn write_flows(
+    path: impl AsRef<Path>,
+    header: &FlowHeader,
+    flows: impl IntoIterator<Item = Flow>,
+) -> Result<usize, Error> {
+    match FlowFormat::from_path(&path)? {
+        FlowFormat::Json => write_flows_json(path, flows),
+        FlowFormat::Csv => write_flows_csv(path, flows),
+        FlowFormat::Binary => write_flows_binary(path, header, flows),
+    }
+}
+
+/// Writes the metadata of a set of flows as JSON, to keep alongside the flows themselves.
+pub fn write_flow_meta(path: impl AsRef<Path>, meta: &FlowMetaTable) -> Result<(), Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut file, meta)?;
+    file.flush()?;
+    Ok(())
+}
+
+pub fn read_flow_meta(path: impl AsRef<Path>) -> Result<FlowMetaTable, Error> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(file)?)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("not a binary flow file")]
+    NotBinaryFlows,
+
+    #[error("unsupported binary flow format version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("the flow file header is too long")]
+    HeaderTooLong,
+
+    #[error("the flow file ends partway through a record")]
+    TruncatedRecord,
+
+    #[error("cannot tell the flow format of {0}; expected a .json, .csv, or .bin extension")]
+    UnknownFormat(String),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon::core::{
+        network::{FlowId, NodeId},
+        units::{Bytes, Nanosecs},
+    };
+
+    use super::*;
+    use crate::flowgen::FlowMeta;
+
+    #[test]
+    fn streamed_json_parses_as_array() -> anyhow::Result<()> {
+        let flows = (0..3)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 10),
+            })
+            .collect::<Vec<_>>();
+        let mut writer = JsonFlowWriter::new(Vec::new())?;
+        for flow in &flows {
+            writer.write(flow)?;
+        }
+        let bytes = writer.finish()?;
+        assert_eq!(bytes, serde_json::to_vec(&flows)?);
+
+        let empty = JsonFlowWriter::new(Vec::new())?.finish()?;
+        assert_eq!(empty, b"[]");
+        Ok(())
+    }
+
+    #[test]
+    fn csv_round_trips() -> anyhow::Result<()> {
+        let flows = (0..3)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i),
+                dst: NodeId::new(7),
+                size: Bytes::new(u64::MAX - i as u64),
+                start: Nanosecs::new(i as u64 * 10),
+            })
+            .collect::<Vec<_>>();
+        let path = std::env::temp_dir().join(format!("flowio-{}.csv", std::process::id()));
+        let nr_written = write_flows(&path, &FlowHeader::default(), flows.clone())?;
+        let text = std::fs::read_to_string(&path);
+        let read = read_flows(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(nr_written, flows.len());
+        assert!(text?.starts_with("id,src,dst,size,start\n"));
+        assert_eq!(read?, (None, flows));
+        Ok(())
+    }
+
+    #[test]
+    fn flow_meta_round_trips() -> anyhow::Result<()> {
+        let meta = [(
+            FlowId::new(2),
+            FlowMeta {
+                class: Some("storage".into()),
+                tags: vec!["incast".into()],
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect::<FlowMetaTable>();
+        let path = std::env::temp_dir().join(format!("flowio-meta-{}.json", std::process::id()));
+        write_flow_meta(&path, &meta)?;
+        let read = read_flow_meta(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(read?, meta);
+        Ok(())
+    }
+
+    #[test]
+    fn binary_round_trips() -> anyhow::Result<()> {
+        let flows = (0..3)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i),
+                dst: NodeId::new(7),
+                size: Bytes::new(u64::MAX - i as u64),
+                start: Nanosecs::new(i as u64 * 10),
+            })
+            .collect::<Vec<_>>();
+        let header = FlowHeader {
+            seed: Some(42),
+            params: serde_json::json!({ "max_load": 0.5 }),
+        };
+        let mut writer = BinaryFlowWriter::new(Vec::new(), &header)?;
+        for flow in &flows {
+            writer.write(flow)?;
+        }
+        let bytes = writer.finish()?;
+
+        let reader = BinaryFlowReader::new(bytes.as_slice())?;
+        assert_eq!(reader.header(), &header);
+        assert_eq!(reader.collect::<Result<Vec<_>, _>>()?, flows);
+
+        let truncated = BinaryFlowReader::new(&bytes[..bytes.len() - 1])?;
+        assert!(matches!(
+            truncated.last(),
+            Some(Err(Error::TruncatedRecord))
+        ));
+        assert!(matches!(
+            BinaryFlowReader::new(b"[]".as_slice()),
+            Err(Error::Io(_))
+        ));
+
+        // A corrupt header length is rejected before anything is allocated for it
+        let mut corrupt = bytes.clone();
+        corrupt[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            BinaryFlowReader::new(corrupt.as_slice()),
+            Err(Error::HeaderTooLong)
+        ));
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
+pub mod anonymize;
 pub mod entry;
 pub mod fabric;
 pub mod flowgen;
+pub mod flowio;
+pub mod reconstruct;
 pub mod spatial;
 
 #[cfg(test)]
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use rustc_hash::FxHashMap;
+use utils::{Ecdf, EcdfError};
+
+use crate::entry::FullEntry;
+
+/// Groups sampled packets into flows.
+///
+/// Packets with the same 5-tuple belong to the same flow unless more than `inactivity_timeout`
+/// passes between consecutive ones, in which case a new flow starts. Flow sizes are estimated by
+/// scaling the bytes of the sampled packets by `sampling_ratio`. Flows none of whose packets were
+/// sampled are missed entirely, so small flows are underrepresented at high sampling ratios.
+#[derive(Debug, Clone, typed_builder::TypedBuilder)]
+pub struct FlowReconstructor {
+    /// In units of `FullEntry::timestamp`.
+    inactivity_timeout: u64,
+    /// One in this many packets was sampled.
+    #[builder(default = 1.0)]
+    sampling_ratio: f64,
+}
+
+impl FlowReconstructor {
+    /// Reconstructs flows from packet samples, which need not be in time order. Flows are
+    /// returned in order of start time.
+    pub fn reconstruct(
+        &self,
+        entries: impl IntoIterator<Item = FullEntry>,
+    ) -> Vec<ReconstructedFlow> {
+        let mut entries = entries.into_iter().collect::<Vec<_>>();
+        entries.sort_by_key(|e| e.timestamp);
+
+        let mut open: FxHashMap<FiveTuple, ReconstructedFlow> = FxHashMap::default();
+        let mut flows = Vec::new();
+        for entry in entries {
+            let tuple = FiveTuple::of(&entry);
+            match open.get_mut(&tuple) {
+                Some(flow) if entry.timestamp - flow.end <= self.inactivity_timeout => {
+                    flow.end = entry.timestamp;
+                    flow.nr_packets += 1;
+                    flow.sampled_bytes += entry.packetlength;
+                }
+                _ => {
+                    let flow = ReconstructedFlow {
+                        tuple: tuple.clone(),
+                        srcrack: entry.srcrack,
+                        dstrack: entry.dstrack,
+                        start: entry.timestamp,
+                        end: entry.timestamp,
+                        nr_packets: 1,
+                        sampled_bytes: entry.packetlength,
+                        size: 0.0,
+                    };
+                    flows.extend(open.insert(tuple, flow));
+                }
+            }
+        }
+        flows.extend(open.into_values());
+        for flow in &mut flows {
+            flow.size = flow.sampled_bytes as f64 * self.sampling_ratio;
+        }
+        flows.sort_by(|a, b| (a.start, &a.tuple).cmp(&(b.start, &b.tuple)));
+        flows
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+pub struct FiveTuple {
+    pub srcip: String,
+    pub dstip: String,
+    pub srcport: String,
+    pub dstport: String,
+    pub ipprotocol: String,
+}
+
+impl FiveTuple {
+    fn of(entry: &FullEntry) -> Self {
+        Self {
+            srcip: entry.srcip.clone(),
+            dstip: entry.dstip.clone(),
+            srcport: entry.srcport.clone(),
+            dstport: entry.dstport.clone(),
+            ipprotocol: entry.ipprotocol.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ReconstructedFlow {
+    pub tuple: FiveTuple,
+    pub srcrack: String,
+    pub dstrack: String,
+    /// Timestamps of the first and last sampled packets.
+    pub start: u64,
+    pub end: u64,
+    /// The number of sampled packets.
+    pub nr_packets: usize,
+    pub sampled_bytes: u64,
+    /// Estimated size in bytes, corrected for sampling.
+    pub size: f64,
+}
+
+/// How to group flows when building size distributions.
+///
+/// Key components have any `%` and `_` percent-encoded, so distinct groups never share a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// By protocol and destination port, keyed as `<protocol>_<port>`.
+    Service,
+    /// By source and destination rack, keyed as `<srcrack>_<dstrack>`.
+    RackPair,
+}
+
+impl GroupBy {
+    fn key(&self, flow: &ReconstructedFlow) -> String {
+        match self {
+            GroupBy::Service => join_key(&flow.tuple.ipprotocol, &flow.tuple.dstport),
+            GroupBy::RackPair => join_key(&flow.srcrack, &flow.dstrack),
+        }
+    }
+}
+
+fn join_key(a: &str, b: &str) -> String {
+    let escape = |s: &str| s.replace('%', "%25").replace('_', "%5F");
+    format!("{}_{}", escape(a), escape(b))
+}
+
+/// Builds an ECDF of flow sizes for each group of flows, skipping groups with fewer than
+/// `min_flows` flows.
+pub fn size_ecdfs(
+    flows: &[ReconstructedFlow],
+    group_by: GroupBy,
+    min_flows: usize,
+) -> Result<BTreeMap<String, Ecdf>, EcdfError> {
+    let mut group2sizes: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for flow in flows {
+        group2sizes
+            .entry(group_by.key(flow))
+            .or_default()
+            .push(flow.size);
+    }
+    group2sizes
+        .into_iter()
+        .filter(|(_, sizes)| sizes.len() >= min_flows.max(1))
+        .map(|(group, sizes)| Ok((group, Ecdf::from_values(&sizes)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(timestamp: u64, srcport: &str, dstport: &str, packetlength: u64) -> FullEntry {
+        FullEntry {
+            timestamp,
+            packetlength,
+            srcip: "a".to_string(),
+            dstip: "b".to_string(),
+            srcport: srcport.to_string(),
+            dstport: dstport.to_string(),
+            ipprotocol: "6".to_string(),
+            srchostprefix: "a".to_string(),
+            dsthostprefix: "b".to_string(),
+            srcrack: "r0".to_string(),
+            dstrack: "r1".to_string(),
+            srcpod: "p0".to_string(),
+            dstpod: "p0".to_string(),
+            intercluster: false,
+            interdatacenter: false,
+        }
+    }
+
+    #[test]
+    fn flows_split_by_tuple_and_timeout() -> anyhow::Result<()> {
+        let packets = vec![
+            packet(0, "1000", "80", 100),
+            packet(3, "1000", "80", 100),
+            packet(1, "1001", "80", 500),
+            // More than the timeout after the last packet, so a new flow
+            packet(20, "1000", "80", 1000),
+            packet(2, "1002", "443", 200),
+        ];
+        let reconstructor = FlowReconstructor::builder()
+            .inactivity_timeout(10)
+            .sampling_ratio(4.0)
+            .build();
+        let flows = reconstructor.reconstruct(packets);
+        let summary = flows
+            .iter()
+            .map(|f| (f.start, f.nr_packets, f.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (0, 2, 800.0),
+                (1, 1, 2000.0),
+                (2, 1, 800.0),
+                (20, 1, 4000.0)
+            ]
+        );
+
+        let ecdfs = size_ecdfs(&flows, GroupBy::Service, 1)?;
+        assert_eq!(ecdfs.keys().collect::<Vec<_>>(), vec!["6_443", "6_80"]);
+        assert_eq!(ecdfs["6_80"].points().len(), 3);
+        let ecdfs = size_ecdfs(&flows, GroupBy::RackPair, 2)?;
+        assert_eq!(ecdfs.keys().collect::<Vec<_>>(), vec!["r0_r1"]);
+        Ok(())
+    }
+
+    #[test]
+    fn group_keys_dont_collide() -> anyhow::Result<()> {
+        let reconstructor = FlowReconstructor::builder().inactivity_timeout(10).build();
+        let mut packets = vec![packet(0, "1000", "80", 100), packet(1, "1001", "80", 100)];
+        (packets[0].srcrack, packets[0].dstrack) = ("a_b".to_string(), "c".to_string());
+        (packets[1].srcrack, packets[1].dstrack) = ("a".to_string(), "b_c".to_string());
+        let flows = reconstructor.reconstruct(packets);
+        let ecdfs = size_ecdfs(&flows, GroupBy::RackPair, 1)?;
+        assert_eq!(ecdfs.keys().collect::<Vec<_>>(), vec!["a%5Fb_c", "a_b%5Fc"]);
+        Ok(())
+    }
+}
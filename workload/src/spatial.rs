@@ -1,12 +1,18 @@
-use std::{ops::AddAssign, path::Path};
+use std::{collections::BTreeMap, path::Path};
 
 use crate::fabric::Cluster;
 use parsimon::core::network::NodeId;
 use rand::prelude::*;
 use rustc_hash::{FxHashMap, FxHashSet};
+use utils::AliasTable;
 
 use crate::entry::Entry;
 
+pub mod analysis;
+mod downsample;
+
+pub use downsample::DownsampleMode;
+
 const NR_TORS_PER_POD: usize = 48;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -15,97 +21,239 @@ pub struct SpatialData {
     pub pod2tors: FxHashMap<String, Vec<String>>,
     pub nr_pods: usize,
     pub nr_racks: usize,
+    /// Per-rack host popularity, keyed by rack name. Only present if requested when building.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_skew: Option<FxHashMap<String, HostSkew>>,
+    /// The trace split into time windows. Only present if requested when building.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub windows: Option<TimeWindows>,
+    /// What the matrix cells count. Files written before this existed count entries.
+    #[serde(default)]
+    pub weighting: Weighting,
+}
+
+/// Options for building [`SpatialData`] from trace entries.
+#[derive(Debug, Clone, Default, typed_builder::TypedBuilder)]
+pub struct SpatialOpts {
+    /// Record how traffic is skewed across the hosts of each rack.
+    #[builder(default)]
+    pub host_skew: bool,
+    /// Also bucket entries into windows spanning this many units of `Entry::timestamp`.
+    #[builder(default, setter(strip_option))]
+    pub window: Option<u64>,
+    /// What each entry contributes to the matrix. Host skew is weighted the same way.
+    #[builder(default)]
+    pub weighting: Weighting,
+}
+
+/// What the cells of a traffic matrix count.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Weighting {
+    /// Trace entries, i.e. sampled packets.
+    #[default]
+    Count,
+    /// Bytes, from `Entry::packetlength`.
+    Bytes,
+    /// Distinct 5-tuples. Entries without ports or protocol are told apart by IPs alone, and no
+    /// inactivity timeout is applied, so long-lived tuples count as a single flow.
+    Flows,
+}
+
+impl Weighting {
+    /// Whether cells measure load rather than numbers of flows. Packets are sampled in proportion
+    /// to the bytes sent, so sampled packet counts measure load too.
+    pub fn is_load(&self) -> bool {
+        matches!(self, Weighting::Count | Weighting::Bytes)
+    }
 }
 
 impl SpatialData {
     pub fn from_csv(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_csv_with(path, &SpatialOpts::default())
+    }
+
+    pub fn from_csv_with(path: impl AsRef<Path>, opts: &SpatialOpts) -> Result<Self, Error> {
         let mut rdr = csv::Reader::from_path(&path)?;
-        let mut pod2tors = FxHashMap::default();
-        let mut rack2rack2count = FxHashMap::default();
+        let mut acc = Accumulator::new(opts)?;
         for result in rdr.deserialize() {
             let entry: Entry = result?;
-            pod2tors
-                .entry(entry.srcpod)
-                .or_insert(FxHashSet::default())
-                .insert(entry.srcrack.clone());
-            pod2tors
-                .entry(entry.dstpod)
-                .or_insert(FxHashSet::default())
-                .insert(entry.dstrack.clone());
-            rack2rack2count
-                .entry(entry.srcrack)
-                .or_insert(FxHashMap::default())
-                .entry(entry.dstrack)
-                .or_insert(0_usize)
-                .add_assign(1);
+            acc.add(entry)?;
         }
-        for tors in pod2tors.values() {
-            if tors.len() > NR_TORS_PER_POD {
-                return Err(Error::TooManyRacks);
-            }
+        acc.finish()
+    }
+
+    pub fn from_entries(
+        entries: impl IntoIterator<Item = Entry>,
+        opts: &SpatialOpts,
+    ) -> Result<Self, Error> {
+        let mut acc = Accumulator::new(opts)?;
+        for entry in entries {
+            acc.add(entry)?;
         }
-        let racks = pod2tors
-            .values()
-            .flat_map(|rack| rack.iter())
-            .collect::<Vec<_>>();
+        acc.finish()
+    }
 
-        // Now each rack is given an index in the matrix
-        let nr_max_tors = pod2tors.len() * NR_TORS_PER_POD;
-        let (idx2name, name2idx): (Vec<_>, FxHashMap<_, _>) = racks
+    /// Like [`SpatialData::from_entries`], for entries that may fail to load. Stops at the first
+    /// error.
+    pub fn try_from_entries<E: From<Error>>(
+        entries: impl IntoIterator<Item = Result<Entry, E>>,
+        opts: &SpatialOpts,
+    ) -> Result<Self, E> {
+        let mut acc = Accumulator::new(opts)?;
+        for entry in entries {
+            acc.add(entry?)?;
+        }
+        Ok(acc.finish()?)
+    }
+
+    /// Aggregates the ToR-level matrix into a pod-to-pod matrix. Rows and columns are indexed by
+    /// pod, in order of pod name.
+    pub fn pod_matrix(&self) -> Tor2TorMatrix {
+        let (pods, idx2pod) = self.pod_indices();
+        let entries = self.matrix.entries().filter_map(|(src, dst, count)| {
+            Some((
+                idx2pod.get(src).copied()??,
+                idx2pod.get(dst).copied()??,
+                count,
+            ))
+        });
+        Tor2TorMatrix::from_entries(pods.len(), entries, pods)
+    }
+
+    /// Classifies each nonzero cell of the matrix by locality, returning `(src, dst, count,
+    /// locality)`. Cells whose racks have no pod are skipped.
+    pub fn localized_entries(&self) -> impl Iterator<Item = (usize, usize, usize, Locality)> + '_ {
+        let (_, idx2pod) = self.pod_indices();
+        self.matrix.entries().filter_map(move |(src, dst, count)| {
+            let src_pod = idx2pod.get(src).copied()??;
+            let dst_pod = idx2pod.get(dst).copied()??;
+            let locality = Locality::classify(src == dst, src_pod == dst_pod);
+            Some((src, dst, count, locality))
+        })
+    }
+
+    /// Returns pod names in sorted order, and the index of each matrix index's pod in that order.
+    fn pod_indices(&self) -> (Vec<String>, Vec<Option<usize>>) {
+        let mut pods = self.pod2tors.keys().cloned().collect::<Vec<_>>();
+        pods.sort();
+        let tor2pod = pods
             .iter()
             .enumerate()
-            .map(|(i, &name)| (name.clone(), (name, i)))
-            .unzip();
+            .flat_map(|(i, pod)| self.pod2tors[pod].iter().map(move |tor| (tor, i)))
+            .collect::<FxHashMap<_, _>>();
+        let idx2pod = self
+            .matrix
+            .idx2name
+            .iter()
+            .map(|name| tor2pod.get(name).copied())
+            .collect::<Vec<_>>();
+        (pods, idx2pod)
+    }
 
-        // Construct the matrix
-        let mut inner = vec![vec![0; nr_max_tors]; nr_max_tors];
-        for (src, dsts) in rack2rack2count {
-            let src = *name2idx.get(&src).unwrap();
-            for (dst, count) in dsts {
-                let dst = *name2idx.get(&dst).unwrap();
-                inner[src][dst] += count;
-            }
-        }
-        let matrix = Tor2TorMatrix::new(inner, idx2name);
-        let nr_pods = pod2tors.len();
-        let nr_racks = racks.len();
-        let pod2tors = pod2tors
-            .into_iter()
-            .map(|(pod, tors)| (pod, tors.into_iter().collect()))
-            .collect();
-        Ok(Self {
-            matrix,
-            pod2tors,
-            nr_pods,
-            nr_racks,
-        })
+    pub fn map_to(&self, cluster: &Cluster, rng: impl Rng) -> Result<SpatialWorkload, Error> {
+        self.map_to_with(cluster, |_| 1.0, rng)
     }
 
-    pub fn map_to(&self, cluster: &Cluster, mut rng: impl Rng) -> Result<SpatialWorkload, Error> {
-        // Collect matrix info
-        let dim = self.matrix.dim();
-        let cumsum = self
+    /// Like [`SpatialData::map_to`], but scales the weight of each cell by `cell_scale` of its
+    /// locality. This turns load-like weights into flow arrival weights when flow sizes depend on
+    /// locality.
+    pub fn map_to_with(
+        &self,
+        cluster: &Cluster,
+        cell_scale: impl Fn(Locality) -> f64,
+        rng: impl Rng,
+    ) -> Result<SpatialWorkload, Error> {
+        let pods = (0..cluster.pods.len()).collect::<Vec<_>>();
+        self.map_onto_pods(cluster, &pods, cell_scale, rng)
+    }
+
+    /// Like [`SpatialData::map_to_with`], but places the spatial data's pods on the cluster pods
+    /// with the given indices only, leaving the rest of the cluster idle. The indices must be
+    /// distinct, one per pod of the spatial data.
+    pub fn map_onto_pods(
+        &self,
+        cluster: &Cluster,
+        pods: &[usize],
+        cell_scale: impl Fn(Locality) -> f64,
+        mut rng: impl Rng,
+    ) -> Result<SpatialWorkload, Error> {
+        if self.windows.as_ref().is_some_and(|w| w.width == 0) {
+            return Err(Error::ZeroWindowWidth);
+        }
+        let name2tor = self.place_racks(cluster, pods, &mut rng)?;
+        let tor2hosts = tor2hosts(cluster);
+
+        // Now chain the `idx2name`, `name2tor`, and `tor2hosts` maps to get an `idx2hosts` map.
+        // With host skew, the ranked trace hosts are placed on randomly chosen cluster hosts.
+        let idx2hosts = self
             .matrix
-            .inner
+            .idx2name
             .iter()
-            .flat_map(|row| row.iter())
-            .scan(0, |acc, x| {
-                *acc += x;
-                Some(*acc)
+            .map(|name| {
+                let tor = name2tor.get(name.as_str()).unwrap();
+                let mut hosts = tor2hosts.get(tor).unwrap().clone();
+                let skew = self.host_skew.as_ref().and_then(|skew| skew.get(name));
+                match skew {
+                    Some(skew) if !hosts.is_empty() => {
+                        hosts.shuffle(&mut rng);
+                        let (src_weights, dst_weights) = skew.fold(hosts.len());
+                        RackHosts::weighted(*tor, hosts, src_weights, dst_weights)
+                    }
+                    _ => RackHosts::uniform(*tor, hosts),
+                }
             })
-            .collect();
+            .collect::<Vec<_>>();
+
+        // Build a sampler for the whole trace, and one for each window. Windows are indexed by
+        // how many window widths they start after the first.
+        let (_, idx2pod) = self.pod_indices();
+        let aggregate = CellSampler::new(&self.matrix, &idx2hosts, &idx2pod, &cell_scale)?;
+        let windows = match &self.windows {
+            Some(windows) => {
+                let first = windows.windows.first().map(|w| w.start).unwrap_or(0);
+                windows
+                    .windows
+                    .iter()
+                    .map(|w| {
+                        let slot = ((w.start - first) / windows.width) as usize;
+                        let sampler =
+                            CellSampler::new(&w.matrix, &idx2hosts, &idx2pod, &cell_scale).ok();
+                        (slot, sampler)
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        Ok(SpatialWorkload {
+            aggregate,
+            windows,
+            idx2hosts,
+        })
+    }
 
-        // Map the matrix onto the cluster. Pods can be placed in an arbitrary order. Give each
-        // pod hash an arbitrary index in `cluster.pods`.
-        if self.pod2tors.len() != cluster.pods.len() {
+    /// Assigns each rack of the spatial data to a ToR of the cluster, placing the spatial data's
+    /// pods on the cluster pods with the given indices. Pods are placed in an arbitrary order,
+    /// and racks randomly within their pod.
+    pub(crate) fn place_racks(
+        &self,
+        cluster: &Cluster,
+        pods: &[usize],
+        mut rng: impl Rng,
+    ) -> Result<FxHashMap<&str, NodeId>, Error> {
+        // Give each pod hash an arbitrary one of the chosen indices in `cluster.pods`.
+        let distinct = pods.iter().collect::<FxHashSet<_>>().len() == pods.len();
+        if self.pod2tors.len() != pods.len()
+            || !distinct
+            || pods.iter().any(|&i| i >= cluster.pods.len())
+        {
             return Err(Error::WorkloadClusterMismatch);
         }
         let pod2idx = self
             .pod2tors
             .keys()
             .cloned()
-            .enumerate()
-            .map(|(i, pod)| (pod, i))
+            .zip(pods.iter().copied())
             .collect::<FxHashMap<_, _>>();
         // Now within a pod, a ToR hash is randomly assigned to a ToR node.
         let name2tor = self
@@ -119,38 +267,10 @@ impl SpatialData {
                     .map(|rack| rack.tor.id)
                     .collect::<Vec<_>>();
                 tor_ids.shuffle(&mut rng);
-                tors.iter().zip(tor_ids)
-            })
-            .collect::<FxHashMap<_, _>>();
-
-        // Get a list of host IDs for each ToR ID.
-        let tor2hosts = cluster
-            .pods
-            .iter()
-            .flat_map(|p| p.racks.iter())
-            .map(|r| {
-                let host_ids = r.hosts.iter().map(|h| h.id).collect::<Vec<_>>();
-                (r.tor.id, host_ids)
+                tors.iter().map(String::as_str).zip(tor_ids)
             })
-            .collect::<FxHashMap<_, _>>();
-
-        // Now chain the `idx2name`, `name2tor`, and `tor2hosts` maps to get an `idx2hosts` map.
-        let idx2hosts = self
-            .matrix
-            .idx2name
-            .iter()
-            .map(|name| {
-                let tor = name2tor.get(name).unwrap();
-                let hosts = tor2hosts.get(tor).unwrap().clone();
-                hosts
-            })
-            .collect::<Vec<_>>();
-
-        Ok(SpatialWorkload {
-            dim,
-            cumsum,
-            idx2hosts,
-        })
+            .collect();
+        Ok(name2tor)
     }
 
     pub fn downsample(&self, nr_pods: usize, nr_tors_per_pod: usize, mut rng: impl Rng) -> Self {
@@ -172,138 +292,1151 @@ impl SpatialData {
                 (pod, tors)
             })
             .collect::<FxHashMap<_, _>>();
+        let downsampled = self.restrict_to(new_pod2tors);
+        assert_eq!(downsampled.nr_racks, nr_pods * nr_tors_per_pod);
+        downsampled
+    }
+
+    /// Keeps only the given pods and racks.
+    fn restrict_to(&self, new_pod2tors: FxHashMap<String, Vec<String>>) -> Self {
         let tors = new_pod2tors
             .values()
             .flat_map(|tors| tors.iter())
             .collect::<FxHashSet<_>>();
-        assert_eq!(tors.len(), nr_pods * nr_tors_per_pod);
 
-        // Find the matrix indices of those pods
-        let keep_indices = self
+        // Find the matrix indices of those racks. Kept racks retain their relative order.
+        let old2new = self
             .matrix
             .idx2name
             .iter()
             .enumerate()
-            .filter_map(|(i, name)| tors.contains(name).then_some(i))
-            .collect::<FxHashSet<_>>();
+            .filter(|&(_, name)| tors.contains(name))
+            .enumerate()
+            .map(|(new, (old, _))| (old, new))
+            .collect::<FxHashMap<_, _>>();
 
         // Construct a new `Tor2TorMatrix`
-        let new_inner = self
-            .matrix
-            .inner
-            .iter()
-            .enumerate()
-            .filter(|&(i, _)| keep_indices.contains(&i))
-            .map(|(_, row)| {
-                row.iter()
-                    .enumerate()
-                    .filter_map(|(i, &count)| keep_indices.contains(&i).then_some(count))
-                    .collect::<Vec<_>>()
-            })
-            .collect::<Vec<_>>();
-        let new_idx2name = keep_indices
-            .iter()
-            .map(|&i| self.matrix.idx2name[i].clone())
-            .collect::<Vec<_>>();
-        let new_matrix = Tor2TorMatrix::new(new_inner, new_idx2name);
+        let mut new_idx2name = vec![String::new(); old2new.len()];
+        for (&old, &new) in &old2new {
+            new_idx2name[new] = self.matrix.idx2name[old].clone();
+        }
+        let new_matrix = self.matrix.restrict(&old2new, new_idx2name);
+        let new_windows = self.windows.as_ref().map(|windows| TimeWindows {
+            width: windows.width,
+            windows: windows
+                .windows
+                .iter()
+                .map(|w| TimeWindow {
+                    start: w.start,
+                    matrix: w.matrix.restrict(&old2new, new_matrix.idx2name.clone()),
+                })
+                .collect(),
+        });
+        let new_host_skew = self.host_skew.as_ref().map(|skew| {
+            skew.iter()
+                .filter(|&(rack, _)| tors.contains(rack))
+                .map(|(rack, skew)| (rack.clone(), skew.clone()))
+                .collect()
+        });
         let nr_racks = tors.len();
         Self {
             matrix: new_matrix,
+            nr_pods: new_pod2tors.len(),
             pod2tors: new_pod2tors,
-            nr_pods,
             nr_racks,
+            host_skew: new_host_skew,
+            windows: new_windows,
+            weighting: self.weighting,
+        }
+    }
+}
+
+/// The host IDs of each ToR of the cluster.
+pub(crate) fn tor2hosts(cluster: &Cluster) -> FxHashMap<NodeId, Vec<NodeId>> {
+    cluster
+        .pods
+        .iter()
+        .flat_map(|p| p.racks.iter())
+        .map(|r| {
+            let host_ids = r.hosts.iter().map(|h| h.id).collect::<Vec<_>>();
+            (r.tor.id, host_ids)
+        })
+        .collect()
+}
+
+/// Where a flow's destination is relative to its source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Locality {
+    IntraRack,
+    IntraPod,
+    InterPod,
+}
+
+impl Locality {
+    pub fn classify(same_rack: bool, same_pod: bool) -> Self {
+        match (same_rack, same_pod) {
+            (true, _) => Locality::IntraRack,
+            (false, true) => Locality::IntraPod,
+            (false, false) => Locality::InterPod,
+        }
+    }
+}
+
+/// Traffic matrices for consecutive time windows of a trace. Every window's matrix shares the
+/// dimensions and rack indices of [`SpatialData::matrix`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TimeWindows {
+    /// The width of each window, in units of `Entry::timestamp`.
+    pub width: u64,
+    /// Windows that saw traffic, in time order.
+    pub windows: Vec<TimeWindow>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TimeWindow {
+    /// The timestamp at which the window starts, a multiple of the window width.
+    pub start: u64,
+    pub matrix: Tor2TorMatrix,
+}
+
+/// How often each host of a rack was seen as a source and as a destination. Hosts are ranked in
+/// decreasing order of total activity; their identities are not kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HostSkew {
+    pub src: Vec<usize>,
+    pub dst: Vec<usize>,
+}
+
+impl HostSkew {
+    /// Folds the ranked trace hosts onto `nr_hosts` slots, returning per-slot source and
+    /// destination weights.
+    fn fold(&self, nr_hosts: usize) -> (Vec<f64>, Vec<f64>) {
+        let fold = |counts: &[usize]| {
+            let mut weights = vec![0.0; nr_hosts];
+            for (rank, &count) in counts.iter().enumerate() {
+                weights[rank % nr_hosts] += count as f64;
+            }
+            weights
+        };
+        (fold(&self.src), fold(&self.dst))
+    }
+}
+
+type Rack2Rack2Count = FxHashMap<String, FxHashMap<String, usize>>;
+type FlowKey = (
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+struct Accumulator<'a> {
+    opts: &'a SpatialOpts,
+    pod2tors: FxHashMap<String, FxHashSet<String>>,
+    rack2rack2count: Rack2Rack2Count,
+    window2rack2rack2count: BTreeMap<u64, Rack2Rack2Count>,
+    // Maps a rack to its hosts' (source, destination) counts
+    rack2host2count: FxHashMap<String, FxHashMap<String, (usize, usize)>>,
+    // Flows seen so far, overall and per window, when weighting by flows
+    seen_flows: FxHashSet<FlowKey>,
+    seen_window_flows: FxHashSet<(u64, FlowKey)>,
+}
+
+impl<'a> Accumulator<'a> {
+    fn new(opts: &'a SpatialOpts) -> Result<Self, Error> {
+        if opts.window == Some(0) {
+            return Err(Error::ZeroWindowWidth);
         }
+        Ok(Self {
+            opts,
+            pod2tors: FxHashMap::default(),
+            rack2rack2count: FxHashMap::default(),
+            window2rack2rack2count: BTreeMap::new(),
+            rack2host2count: FxHashMap::default(),
+            seen_flows: FxHashSet::default(),
+            seen_window_flows: FxHashSet::default(),
+        })
+    }
+
+    fn add(&mut self, entry: Entry) -> Result<(), Error> {
+        // What this entry adds to the whole trace and to its window
+        let (weight, window_weight) = match self.opts.weighting {
+            Weighting::Count => (1, 1),
+            Weighting::Bytes => {
+                let len = entry
+                    .packetlength
+                    .ok_or(Error::MissingField("packetlength"))?;
+                (len as usize, len as usize)
+            }
+            Weighting::Flows => {
+                let key = (
+                    entry.srcip.clone(),
+                    entry.dstip.clone(),
+                    entry.srcport.clone(),
+                    entry.dstport.clone(),
+                    entry.ipprotocol.clone(),
+                );
+                let window_new = match self.opts.window {
+                    Some(width) => self
+                        .seen_window_flows
+                        .insert((entry.timestamp / width, key.clone())),
+                    None => false,
+                };
+                let new = self.seen_flows.insert(key);
+                (new as usize, window_new as usize)
+            }
+        };
+        if let Some(width) = self.opts.window {
+            *self
+                .window2rack2rack2count
+                .entry(entry.timestamp / width)
+                .or_default()
+                .entry(entry.srcrack.clone())
+                .or_default()
+                .entry(entry.dstrack.clone())
+                .or_default() += window_weight;
+        }
+        if self.opts.host_skew {
+            self.rack2host2count
+                .entry(entry.srcrack.clone())
+                .or_default()
+                .entry(entry.srcip)
+                .or_default()
+                .0 += weight;
+            self.rack2host2count
+                .entry(entry.dstrack.clone())
+                .or_default()
+                .entry(entry.dstip)
+                .or_default()
+                .1 += weight;
+        }
+        self.pod2tors
+            .entry(entry.srcpod)
+            .or_default()
+            .insert(entry.srcrack.clone());
+        self.pod2tors
+            .entry(entry.dstpod)
+            .or_default()
+            .insert(entry.dstrack.clone());
+        *self
+            .rack2rack2count
+            .entry(entry.srcrack)
+            .or_default()
+            .entry(entry.dstrack)
+            .or_default() += weight;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<SpatialData, Error> {
+        let pod2tors = self.pod2tors;
+        for tors in pod2tors.values() {
+            if tors.len() > NR_TORS_PER_POD {
+                return Err(Error::TooManyRacks);
+            }
+        }
+        let racks = pod2tors
+            .values()
+            .flat_map(|rack| rack.iter())
+            .collect::<Vec<_>>();
+
+        // Now each rack is given an index in the matrix
+        let nr_max_tors = pod2tors.len() * NR_TORS_PER_POD;
+        let (idx2name, name2idx): (Vec<_>, FxHashMap<_, _>) = racks
+            .iter()
+            .enumerate()
+            .map(|(i, &name)| (name.clone(), (name, i)))
+            .unzip();
+
+        // Construct the matrix, and one per window
+        let to_matrix = |rack2rack2count: Rack2Rack2Count| {
+            let mut entries = Vec::new();
+            for (src, dsts) in rack2rack2count {
+                let src = *name2idx.get(&src).unwrap();
+                for (dst, count) in dsts {
+                    let dst = *name2idx.get(&dst).unwrap();
+                    entries.push((src, dst, count));
+                }
+            }
+            Tor2TorMatrix::from_entries(nr_max_tors, entries, idx2name.clone())
+        };
+        let matrix = to_matrix(self.rack2rack2count);
+        let windows = self.opts.window.map(|width| TimeWindows {
+            width,
+            windows: self
+                .window2rack2rack2count
+                .into_iter()
+                .map(|(i, rack2rack2count)| TimeWindow {
+                    start: i * width,
+                    matrix: to_matrix(rack2rack2count),
+                })
+                .collect(),
+        });
+
+        // Rank each rack's hosts by total activity, breaking ties by name for determinism
+        let host_skew = self.opts.host_skew.then(|| {
+            self.rack2host2count
+                .into_iter()
+                .map(|(rack, host2count)| {
+                    let mut hosts = host2count.into_iter().collect::<Vec<_>>();
+                    hosts.sort_by(|(a, (a_src, a_dst)), (b, (b_src, b_dst))| {
+                        (b_src + b_dst).cmp(&(a_src + a_dst)).then(a.cmp(b))
+                    });
+                    let (src, dst) = hosts.into_iter().map(|(_, counts)| counts).unzip();
+                    (rack, HostSkew { src, dst })
+                })
+                .collect()
+        });
+
+        let nr_pods = pod2tors.len();
+        let nr_racks = racks.len();
+        let pod2tors = pod2tors
+            .into_iter()
+            .map(|(pod, tors)| (pod, tors.into_iter().collect()))
+            .collect();
+        Ok(SpatialData {
+            matrix,
+            pod2tors,
+            nr_pods,
+            nr_racks,
+            host_skew,
+            windows,
+            weighting: self.opts.weighting,
+        })
     }
 }
 
-#[derive(Debug, derive_new::new, serde::Serialize, serde::Deserialize)]
+/// A ToR-to-ToR traffic matrix, stored sparsely in compressed sparse row (CSR) form.
+///
+/// On disk the matrix is written as a list of `[src, dst, count]` triples for the nonzero cells.
+/// The legacy dense form (`"inner": [[...], ...]`) is still accepted when deserializing.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "MatrixRepr", into = "MatrixRepr")]
 pub struct Tor2TorMatrix {
-    pub inner: Vec<Vec<usize>>,
+    dim: usize,
+    // The nonzero cells of row `i` are at `row_ptr[i]..row_ptr[i + 1]` in `col_idx` and `counts`
+    row_ptr: Vec<usize>,
+    col_idx: Vec<usize>,
+    counts: Vec<usize>,
     pub idx2name: Vec<String>,
 }
 
 impl Tor2TorMatrix {
+    /// Builds a matrix from `(src, dst, count)` triples. Duplicate cells are summed and zero
+    /// counts are dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any index is out of bounds for `dim`.
+    pub fn from_entries(
+        dim: usize,
+        entries: impl IntoIterator<Item = (usize, usize, usize)>,
+        idx2name: Vec<String>,
+    ) -> Self {
+        let mut entries = entries
+            .into_iter()
+            .filter(|&(_, _, count)| count > 0)
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|&(src, dst, _)| (src, dst));
+        let mut row_ptr = vec![0; dim + 1];
+        let mut col_idx = Vec::with_capacity(entries.len());
+        let mut counts: Vec<usize> = Vec::with_capacity(entries.len());
+        let mut last = None;
+        for (src, dst, count) in entries {
+            assert!(src < dim && dst < dim, "matrix index out of bounds");
+            if last == Some((src, dst)) {
+                *counts.last_mut().unwrap() += count;
+                continue;
+            }
+            row_ptr[src + 1] += 1;
+            col_idx.push(dst);
+            counts.push(count);
+            last = Some((src, dst));
+        }
+        for i in 0..dim {
+            row_ptr[i + 1] += row_ptr[i];
+        }
+        Self {
+            dim,
+            row_ptr,
+            col_idx,
+            counts,
+            idx2name,
+        }
+    }
+
+    /// Builds a matrix from a dense, square `inner[src][dst]` representation.
+    pub fn from_dense(inner: Vec<Vec<usize>>, idx2name: Vec<String>) -> Self {
+        let dim = inner.len();
+        let entries = inner.into_iter().enumerate().flat_map(|(src, row)| {
+            row.into_iter()
+                .enumerate()
+                .map(move |(dst, count)| (src, dst, count))
+        });
+        Self::from_entries(dim, entries, idx2name)
+    }
+
     pub fn dim(&self) -> usize {
-        self.inner.len()
+        self.dim
+    }
+
+    /// The number of nonzero cells.
+    pub fn nnz(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn get(&self, src: usize, dst: usize) -> usize {
+        let (cols, counts) = self.row_slices(src);
+        cols.binary_search(&dst).map(|i| counts[i]).unwrap_or(0)
+    }
+
+    /// Iterates over the nonzero cells of row `src` as `(dst, count)` pairs.
+    pub fn row(&self, src: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let (cols, counts) = self.row_slices(src);
+        cols.iter().copied().zip(counts.iter().copied())
+    }
+
+    /// Iterates over all nonzero cells as `(src, dst, count)` triples in row-major order.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        (0..self.dim).flat_map(move |src| self.row(src).map(move |(dst, count)| (src, dst, count)))
+    }
+
+    /// Keeps only the rows and columns in `old2new`, renumbering them accordingly.
+    fn restrict(&self, old2new: &FxHashMap<usize, usize>, idx2name: Vec<String>) -> Self {
+        let entries = self.entries().filter_map(|(src, dst, count)| {
+            let src = *old2new.get(&src)?;
+            let dst = *old2new.get(&dst)?;
+            Some((src, dst, count))
+        });
+        Self::from_entries(old2new.len(), entries, idx2name)
+    }
+
+    pub fn to_dense(&self) -> Vec<Vec<usize>> {
+        let mut inner = vec![vec![0; self.dim]; self.dim];
+        for (src, dst, count) in self.entries() {
+            inner[src][dst] = count;
+        }
+        inner
     }
 
     pub fn row_weights(&self) -> impl Iterator<Item = f64> + '_ {
-        let sum = self.inner_sum();
-        self.inner
-            .iter()
-            .map(move |v| v.iter().sum::<usize>() as f64 / sum as f64)
+        let sum = self.total();
+        (0..self.dim).map(move |i| {
+            let (_, counts) = self.row_slices(i);
+            counts.iter().sum::<usize>() as f64 / sum as f64
+        })
     }
 
     pub fn col_weights(&self) -> impl Iterator<Item = f64> + '_ {
-        let sum = self.inner_sum();
-        let n = self.dim();
-        (0..n).map(move |i| self.inner.iter().map(|v| v[i]).sum::<usize>() as f64 / sum as f64)
+        let sum = self.total();
+        let mut cols = vec![0_usize; self.dim];
+        for (&dst, &count) in self.col_idx.iter().zip(&self.counts) {
+            cols[dst] += count;
+        }
+        cols.into_iter().map(move |c| c as f64 / sum as f64)
     }
 
     pub fn diag_weight(&self) -> f64 {
-        let sum = self.inner_sum();
-        let n = self.dim();
-        (0..n).map(|i| self.inner[i][i]).sum::<usize>() as f64 / sum as f64
+        let sum = self.total();
+        (0..self.dim).map(|i| self.get(i, i)).sum::<usize>() as f64 / sum as f64
     }
 
     pub fn off_diag_weight(&self) -> f64 {
         1.0 - self.diag_weight()
     }
 
-    fn inner_sum(&self) -> usize {
-        self.inner.iter().flat_map(|v| v.iter()).sum()
+    /// The sum of all cells.
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    fn row_slices(&self, src: usize) -> (&[usize], &[usize]) {
+        let range = self.row_ptr[src]..self.row_ptr[src + 1];
+        (&self.col_idx[range.clone()], &self.counts[range])
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum MatrixRepr {
+    Sparse {
+        dim: usize,
+        entries: Vec<(usize, usize, usize)>,
+        idx2name: Vec<String>,
+    },
+    Dense {
+        inner: Vec<Vec<usize>>,
+        idx2name: Vec<String>,
+    },
+}
+
+impl TryFrom<MatrixRepr> for Tor2TorMatrix {
+    type Error = Error;
+
+    fn try_from(repr: MatrixRepr) -> Result<Self, Self::Error> {
+        match repr {
+            MatrixRepr::Sparse {
+                dim,
+                entries,
+                idx2name,
+            } => {
+                if entries
+                    .iter()
+                    .any(|&(src, dst, _)| src >= dim || dst >= dim)
+                {
+                    return Err(Error::InvalidMatrix);
+                }
+                Ok(Self::from_entries(dim, entries, idx2name))
+            }
+            MatrixRepr::Dense { inner, idx2name } => {
+                if inner.iter().any(|row| row.len() != inner.len()) {
+                    return Err(Error::InvalidMatrix);
+                }
+                Ok(Self::from_dense(inner, idx2name))
+            }
+        }
+    }
+}
+
+impl From<Tor2TorMatrix> for MatrixRepr {
+    fn from(matrix: Tor2TorMatrix) -> Self {
+        MatrixRepr::Sparse {
+            dim: matrix.dim,
+            entries: matrix.entries().collect(),
+            idx2name: matrix.idx2name,
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct SpatialWorkload {
-    dim: usize,
-    cumsum: Vec<usize>,
-    // Maps an index in the original `Tor2TorMatrix` to a list of host IDs
-    idx2hosts: Vec<Vec<NodeId>>,
+    aggregate: CellSampler,
+    // Per-window samplers, keyed by window slot in increasing order. `None` marks a window
+    // without samplable cells, which falls back to the aggregate.
+    windows: Vec<(usize, Option<CellSampler>)>,
+    // Maps an index in the original `Tor2TorMatrix` to the hosts of that rack
+    idx2hosts: Vec<RackHosts>,
 }
 
 impl SpatialWorkload {
-    pub fn sample(&self, mut rng: impl Rng) -> (NodeId, NodeId) {
-        let tot = *self.cumsum.last().unwrap();
-        let random = rng.gen_range(0..tot);
-        let index = match self.cumsum.binary_search(&random) {
-            Ok(i) => {
-                // `random` was found at `i`
-                let mut index = i;
-                while random >= self.cumsum[index] {
-                    index += 1;
+    /// Samples a host pair from the matrix of the whole trace.
+    pub fn sample(&self, rng: impl Rng) -> (NodeId, NodeId) {
+        let (src, dst, _) = self.sample_with(&self.aggregate, rng);
+        (src, dst)
+    }
+
+    /// Samples a host pair from the window in effect `slot` window widths after the first
+    /// window. Gaps in the trace keep the preceding window, and slots past the end keep the
+    /// last. Without windows, this is the same as [`SpatialWorkload::sample`].
+    pub fn sample_at(&self, slot: usize, rng: impl Rng) -> (NodeId, NodeId) {
+        let (src, dst, _) = self.sample_at_with_locality(slot, rng);
+        (src, dst)
+    }
+
+    /// Like [`SpatialWorkload::sample`], also returning the locality of the pair's racks in the
+    /// trace.
+    pub fn sample_with_locality(&self, rng: impl Rng) -> (NodeId, NodeId, Locality) {
+        self.sample_with(&self.aggregate, rng)
+    }
+
+    /// Like [`SpatialWorkload::sample_at`], also returning the locality of the pair's racks in
+    /// the trace.
+    pub fn sample_at_with_locality(
+        &self,
+        slot: usize,
+        rng: impl Rng,
+    ) -> (NodeId, NodeId, Locality) {
+        let i = self.windows.partition_point(|&(s, _)| s <= slot);
+        let sampler = i
+            .checked_sub(1)
+            .and_then(|i| self.windows[i].1.as_ref())
+            .unwrap_or(&self.aggregate);
+        self.sample_with(sampler, rng)
+    }
+
+    pub fn nr_windows(&self) -> usize {
+        self.windows.len()
+    }
+
+    /// Replaces the scale applied to each cell's weight, as given to
+    /// [`SpatialData::map_to_with`]. Windows left with no weight fall back to the aggregate.
+    pub fn rescale(&mut self, cell_scale: impl Fn(Locality) -> f64) -> Result<(), Error> {
+        self.aggregate.rescale(&cell_scale)?;
+        for (_, window) in &mut self.windows {
+            if let Some(sampler) = window {
+                if sampler.rescale(&cell_scale).is_err() {
+                    *window = None;
                 }
-                index
-            }
-            Err(i) => {
-                // `random` could be placed at `i`
-                i
             }
+        }
+        Ok(())
+    }
+
+    /// The probability that a sample from the whole trace has each locality.
+    pub fn locality_fractions(&self) -> FxHashMap<Locality, f64> {
+        let total = self.aggregate.weights.iter().sum::<f64>();
+        let mut fractions = FxHashMap::default();
+        for (&(_, _, locality), weight) in self.aggregate.cells.iter().zip(&self.aggregate.weights)
+        {
+            *fractions.entry(locality).or_default() += weight / total;
+        }
+        fractions
+    }
+
+    /// The exact traffic of each cell of the whole trace, for computing expected loads without
+    /// sampling.
+    pub(crate) fn cell_traffic(&self) -> Vec<CellTraffic> {
+        let sampler = &self.aggregate;
+        let total = sampler.weights.iter().sum::<f64>();
+        sampler
+            .cells
+            .iter()
+            .zip(&sampler.weights)
+            .filter(|&(_, &weight)| weight > 0.0)
+            .map(|(&(src_idx, dst_idx, _), weight)| {
+                let (src_rack, dst_rack) = (&self.idx2hosts[src_idx], &self.idx2hosts[dst_idx]);
+                let src_probs = src_rack.src_probs();
+                let dst_probs = if src_idx == dst_idx {
+                    // The destination is drawn given the source
+                    let mut probs = vec![0.0; dst_rack.hosts.len()];
+                    for (src, p_src) in src_probs.iter().enumerate() {
+                        for (dst, p_dst) in dst_rack.dst_probs_except(src).into_iter().enumerate() {
+                            probs[dst] += p_src * p_dst;
+                        }
+                    }
+                    probs
+                } else {
+                    dst_rack.dst_probs()
+                };
+                CellTraffic {
+                    prob: weight / total,
+                    src_tor: src_rack.tor,
+                    dst_tor: dst_rack.tor,
+                    src: src_rack.hosts.iter().copied().zip(src_probs).collect(),
+                    dst: dst_rack.hosts.iter().copied().zip(dst_probs).collect(),
+                }
+            })
+            .collect()
+    }
+
+    fn sample_with(&self, sampler: &CellSampler, mut rng: impl Rng) -> (NodeId, NodeId, Locality) {
+        let (src_idx, dst_idx, locality) = sampler.sample(&mut rng);
+        let src_rack = &self.idx2hosts[src_idx];
+        let src_i = src_rack.sample_src(&mut rng);
+        let dst_i = if src_idx == dst_idx {
+            src_rack.sample_dst_except(src_i, &mut rng)
+        } else {
+            self.idx2hosts[dst_idx].sample_dst(&mut rng)
         };
-        let (src_idx, dst_idx) = (index / self.dim, index % self.dim);
-        let (src_choices, dst_choices) = (&self.idx2hosts[src_idx], &self.idx2hosts[dst_idx]);
-        let mut src_host = NodeId::new(0);
-        let mut dst_host = NodeId::new(0);
-        while src_host == dst_host {
-            src_host = *src_choices.choose(&mut rng).unwrap();
-            dst_host = *dst_choices.choose(&mut rng).unwrap();
+        let (src, dst) = (src_rack.hosts[src_i], self.idx2hosts[dst_idx].hosts[dst_i]);
+        (src, dst, locality)
+    }
+}
+
+/// One rack pair of a [`SpatialWorkload`]: the probability that a sampled flow falls in it, and
+/// the marginal probability of each host of the pair's racks being the source and destination.
+#[derive(Debug, Clone)]
+pub(crate) struct CellTraffic {
+    pub(crate) prob: f64,
+    pub(crate) src_tor: NodeId,
+    pub(crate) dst_tor: NodeId,
+    pub(crate) src: Vec<(NodeId, f64)>,
+    pub(crate) dst: Vec<(NodeId, f64)>,
+}
+
+/// Samples the `(src, dst)` cells of a `Tor2TorMatrix` in proportion to their counts, each
+/// scaled by a factor that depends on the cell's locality.
+#[derive(Debug)]
+struct CellSampler {
+    cells: Vec<(usize, usize, Locality)>,
+    counts: Vec<f64>,
+    // The scaled counts
+    weights: Vec<f64>,
+    table: AliasTable,
+}
+
+impl CellSampler {
+    /// Cells that cannot produce two distinct hosts (a rack without hosts, or an intra-rack cell
+    /// of a single-host rack) are dropped, and it is an error if no samplable cell remains.
+    fn new(
+        matrix: &Tor2TorMatrix,
+        idx2hosts: &[RackHosts],
+        idx2pod: &[Option<usize>],
+        scale: impl Fn(Locality) -> f64,
+    ) -> Result<Self, Error> {
+        let (cells, counts): (Vec<_>, Vec<_>) = matrix
+            .entries()
+            .filter(|&(src, dst, _)| {
+                let (nr_src, nr_dst) = (idx2hosts[src].hosts.len(), idx2hosts[dst].hosts.len());
+                nr_src > 0 && nr_dst > 0 && (src != dst || nr_src > 1)
+            })
+            .map(|(src, dst, count)| {
+                let locality = Locality::classify(src == dst, idx2pod[src] == idx2pod[dst]);
+                ((src, dst, locality), count as f64)
+            })
+            .unzip();
+        let (weights, table) = Self::table(&cells, &counts, scale)?;
+        Ok(Self {
+            cells,
+            counts,
+            weights,
+            table,
+        })
+    }
+
+    fn rescale(&mut self, scale: impl Fn(Locality) -> f64) -> Result<(), Error> {
+        (self.weights, self.table) = Self::table(&self.cells, &self.counts, scale)?;
+        Ok(())
+    }
+
+    fn table(
+        cells: &[(usize, usize, Locality)],
+        counts: &[f64],
+        scale: impl Fn(Locality) -> f64,
+    ) -> Result<(Vec<f64>, AliasTable), Error> {
+        let weights = cells
+            .iter()
+            .zip(counts)
+            .map(|(&(_, _, locality), count)| count * scale(locality))
+            .collect::<Vec<_>>();
+        let table = AliasTable::new(&weights).map_err(|_| Error::NoSamplableCells)?;
+        Ok((weights, table))
+    }
+
+    fn sample(&self, mut rng: impl Rng) -> (usize, usize, Locality) {
+        self.cells[self.table.sample(&mut rng)]
+    }
+}
+
+/// The hosts of one rack, and how to pick among them.
+#[derive(Debug)]
+struct RackHosts {
+    tor: NodeId,
+    hosts: Vec<NodeId>,
+    // `None` means hosts are picked uniformly
+    src: Option<(AliasTable, Vec<f64>)>,
+    dst: Option<(AliasTable, Vec<f64>)>,
+}
+
+impl RackHosts {
+    fn uniform(tor: NodeId, hosts: Vec<NodeId>) -> Self {
+        Self {
+            tor,
+            hosts,
+            src: None,
+            dst: None,
+        }
+    }
+
+    /// Falls back to uniform choice for a direction whose weights are all zero.
+    fn weighted(
+        tor: NodeId,
+        hosts: Vec<NodeId>,
+        src_weights: Vec<f64>,
+        dst_weights: Vec<f64>,
+    ) -> Self {
+        Self {
+            tor,
+            hosts,
+            src: AliasTable::new(&src_weights)
+                .ok()
+                .map(|table| (table, src_weights)),
+            dst: AliasTable::new(&dst_weights)
+                .ok()
+                .map(|table| (table, dst_weights)),
+        }
+    }
+
+    fn sample_src(&self, mut rng: impl Rng) -> usize {
+        match &self.src {
+            Some((table, _)) => table.sample(&mut rng),
+            None => rng.gen_range(0..self.hosts.len()),
+        }
+    }
+
+    fn sample_dst(&self, mut rng: impl Rng) -> usize {
+        match &self.dst {
+            Some((table, _)) => table.sample(&mut rng),
+            None => rng.gen_range(0..self.hosts.len()),
+        }
+    }
+
+    /// The probabilities with which `sample_src` picks each host.
+    fn src_probs(&self) -> Vec<f64> {
+        match &self.src {
+            Some((_, weights)) => normalized(weights),
+            None => vec![1.0 / self.hosts.len() as f64; self.hosts.len()],
+        }
+    }
+
+    /// The probabilities with which `sample_dst` picks each host.
+    fn dst_probs(&self) -> Vec<f64> {
+        match &self.dst {
+            Some((_, weights)) => normalized(weights),
+            None => vec![1.0 / self.hosts.len() as f64; self.hosts.len()],
+        }
+    }
+
+    /// The probabilities with which `sample_dst_except` picks each host.
+    fn dst_probs_except(&self, src: usize) -> Vec<f64> {
+        if let Some((_, weights)) = &self.dst {
+            let total = weights.iter().sum::<f64>() - weights[src];
+            if total > 0.0 {
+                let mut probs = weights.iter().map(|w| w / total).collect::<Vec<_>>();
+                probs[src] = 0.0;
+                return probs;
+            }
+        }
+        let mut probs = vec![1.0 / (self.hosts.len() - 1) as f64; self.hosts.len()];
+        probs[src] = 0.0;
+        probs
+    }
+
+    /// Picks a destination other than `src`. The rack must have at least two hosts.
+    fn sample_dst_except(&self, src: usize, mut rng: impl Rng) -> usize {
+        if let Some((_, weights)) = &self.dst {
+            // Walk the weights with `src` removed; this is linear in the rack size, but racks are
+            // small and it avoids rejection loops when `src` holds most of the weight.
+            let total = weights.iter().sum::<f64>() - weights[src];
+            if total > 0.0 {
+                let mut random = rng.gen_range(0.0..total);
+                let mut last = src;
+                for (i, &w) in weights.iter().enumerate() {
+                    if i == src || w == 0.0 {
+                        continue;
+                    }
+                    if random < w {
+                        return i;
+                    }
+                    random -= w;
+                    last = i;
+                }
+                return last;
+            }
+        }
+        let mut dst = rng.gen_range(0..self.hosts.len() - 1);
+        if dst >= src {
+            dst += 1;
         }
-        (src_host, dst_host)
+        dst
     }
 }
 
+fn normalized(weights: &[f64]) -> Vec<f64> {
+    let total = weights.iter().sum::<f64>();
+    weights.iter().map(|w| w / total).collect()
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("too many racks for the number of pods in the dataset")]
     TooManyRacks,
 
+    #[error("not enough pods or racks to downsample to the requested size")]
+    TooFewRacks,
+
+    #[error("cannot downsample to zero pods or zero racks per pod")]
+    EmptyDownsample,
+
     #[error("cannot map spatial workload to cluster")]
     WorkloadClusterMismatch,
 
+    #[error("invalid traffic matrix")]
+    InvalidMatrix,
+
+    #[error("no cell of the traffic matrix can produce a pair of distinct hosts")]
+    NoSamplableCells,
+
+    #[error("time windows must have a positive width")]
+    ZeroWindowWidth,
+
+    #[error("entry is missing `{0}`, which the requested weighting needs")]
+    MissingField(&'static str),
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
     #[error(transparent)]
     Csv(#[from] csv::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TINY_CLUSTER;
+
+    use super::*;
+
+    fn names(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("r{i}")).collect()
+    }
+
+    #[test]
+    fn dense_and_sparse_forms_agree() -> anyhow::Result<()> {
+        let inner = vec![vec![3, 0, 1], vec![0, 0, 0], vec![2, 4, 0]];
+        let dense = serde_json::json!({ "inner": inner, "idx2name": names(3) });
+        let from_dense: Tor2TorMatrix = serde_json::from_value(dense)?;
+        assert_eq!(from_dense.nnz(), 4);
+        assert_eq!(from_dense.to_dense(), inner);
+
+        let sparse = serde_json::to_value(&from_dense)?;
+        assert_eq!(
+            sparse["entries"],
+            serde_json::json!([[0, 0, 3], [0, 2, 1], [2, 0, 2], [2, 1, 4]])
+        );
+        let from_sparse: Tor2TorMatrix = serde_json::from_value(sparse)?;
+        assert_eq!(from_sparse.to_dense(), inner);
+        Ok(())
+    }
+
+    #[test]
+    fn weights_correct() {
+        let matrix = Tor2TorMatrix::from_entries(
+            3,
+            [(0, 0, 3), (0, 2, 1), (2, 0, 2), (2, 1, 2), (2, 1, 2)],
+            names(3),
+        );
+        assert_eq!(matrix.get(2, 1), 4);
+        assert_eq!(
+            matrix.row_weights().collect::<Vec<_>>(),
+            vec![0.4, 0.0, 0.6]
+        );
+        assert_eq!(
+            matrix.col_weights().collect::<Vec<_>>(),
+            vec![0.5, 0.4, 0.1]
+        );
+        assert_eq!(matrix.diag_weight(), 0.3);
+    }
+
+    fn tiny_spatial(entries: &[(usize, usize, usize)]) -> SpatialData {
+        let pod2tors = [
+            ("p0".to_string(), names(2)),
+            ("p1".to_string(), vec!["r2".to_string(), "r3".to_string()]),
+        ]
+        .into_iter()
+        .collect();
+        SpatialData {
+            matrix: Tor2TorMatrix::from_entries(4, entries.iter().copied(), names(4)),
+            pod2tors,
+            nr_pods: 2,
+            nr_racks: 4,
+            host_skew: None,
+            windows: None,
+            weighting: Weighting::Count,
+        }
+    }
+
+    fn entry(src: (&str, &str, &str), dst: (&str, &str, &str)) -> Entry {
+        Entry {
+            timestamp: 0,
+            srcip: src.0.to_string(),
+            dstip: dst.0.to_string(),
+            srcrack: src.1.to_string(),
+            dstrack: dst.1.to_string(),
+            srcpod: src.2.to_string(),
+            dstpod: dst.2.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn host_skew_preserved() -> anyhow::Result<()> {
+        let cluster: Cluster = serde_json::from_str(TINY_CLUSTER)?;
+        let (a, b, c) = (("a", "r0", "p0"), ("b", "r0", "p0"), ("c", "r2", "p1"));
+        let entries = std::iter::repeat_with(|| entry(a, c))
+            .take(90)
+            .chain(std::iter::repeat_with(|| entry(b, c)).take(10));
+        let opts = SpatialOpts::builder().host_skew(true).build();
+        let spatial = SpatialData::from_entries(entries, &opts)?;
+        let skew = spatial.host_skew.as_ref().unwrap();
+        assert_eq!(skew["r0"].src, vec![90, 10]);
+        assert_eq!(skew["r2"].dst, vec![100]);
+
+        let pod_matrix = spatial.pod_matrix();
+        assert_eq!(pod_matrix.idx2name, vec!["p0", "p1"]);
+        assert_eq!(pod_matrix.entries().collect::<Vec<_>>(), vec![(0, 1, 100)]);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let workload = spatial.map_to(&cluster, &mut rng)?;
+        let nr_samples = 10_000;
+        let mut src2count = FxHashMap::default();
+        for _ in 0..nr_samples {
+            let (src, _) = workload.sample(&mut rng);
+            *src2count.entry(src).or_insert(0) += 1;
+        }
+        let max = *src2count.values().max().unwrap() as f64 / nr_samples as f64;
+        assert!((max - 0.9).abs() < 0.02, "top host fraction {max}");
+        Ok(())
+    }
+
+    #[test]
+    fn sample_follows_weights() -> anyhow::Result<()> {
+        let cluster: Cluster = serde_json::from_str(TINY_CLUSTER)?;
+        let spatial = tiny_spatial(&[(0, 0, 1), (0, 3, 3)]);
+        let mut rng = StdRng::seed_from_u64(0);
+        let workload = spatial.map_to(&cluster, &mut rng)?;
+        let nr_samples = 100_000;
+        let mut nr_intra = 0;
+        for _ in 0..nr_samples {
+            let (src, dst) = workload.sample(&mut rng);
+            assert_ne!(src, dst);
+            if src.inner() / 2 == dst.inner() / 2 {
+                nr_intra += 1;
+            }
+        }
+        let frac = nr_intra as f64 / nr_samples as f64;
+        assert!((frac - 0.25).abs() < 0.01, "intra-rack fraction {frac}");
+        Ok(())
+    }
+
+    #[test]
+    fn degenerate_cells_dropped() -> anyhow::Result<()> {
+        let mut cluster: Cluster = serde_json::from_str(TINY_CLUSTER)?;
+        for pod in &mut cluster.pods {
+            for rack in &mut pod.racks {
+                rack.hosts.truncate(1);
+            }
+        }
+        let mut rng = StdRng::seed_from_u64(0);
+        let spatial = tiny_spatial(&[(0, 0, 5), (1, 2, 1)]);
+        let workload = spatial.map_to(&cluster, &mut rng)?;
+        for _ in 0..1_000 {
+            let (src, dst) = workload.sample(&mut rng);
+            assert_ne!(src, dst);
+        }
+        let spatial = tiny_spatial(&[(0, 0, 5), (3, 3, 1)]);
+        assert!(matches!(
+            spatial.map_to(&cluster, &mut rng),
+            Err(Error::NoSamplableCells)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn localities_sampled_and_rescaled() -> anyhow::Result<()> {
+        let cluster: Cluster = serde_json::from_str(TINY_CLUSTER)?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let spatial = tiny_spatial(&[(0, 0, 1), (0, 1, 1), (0, 2, 2)]);
+        let mut workload = spatial.map_to(&cluster, &mut rng)?;
+        let fractions = workload.locality_fractions();
+        assert_eq!(fractions[&Locality::IntraRack], 0.25);
+        assert_eq!(fractions[&Locality::InterPod], 0.5);
+        // Racks hold two hosts each, and pods two racks
+        for _ in 0..1_000 {
+            let (src, dst, locality) = workload.sample_with_locality(&mut rng);
+            let (src, dst) = (src.inner(), dst.inner());
+            assert_eq!(
+                locality,
+                Locality::classify(src / 2 == dst / 2, src / 4 == dst / 4)
+            );
+        }
+
+        workload.rescale(|locality| match locality {
+            Locality::InterPod => 0.0,
+            _ => 1.0,
+        })?;
+        assert_eq!(workload.locality_fractions()[&Locality::InterPod], 0.0);
+        for _ in 0..1_000 {
+            let (_, _, locality) = workload.sample_with_locality(&mut rng);
+            assert_ne!(locality, Locality::InterPod);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_pods_rejected() -> anyhow::Result<()> {
+        let cluster: Cluster = serde_json::from_str(TINY_CLUSTER)?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let spatial = tiny_spatial(&[(0, 2, 1)]);
+        assert!(spatial
+            .map_onto_pods(&cluster, &[1, 0], |_| 1.0, &mut rng)
+            .is_ok());
+        assert!(matches!(
+            spatial.map_onto_pods(&cluster, &[1, 1], |_| 1.0, &mut rng),
+            Err(Error::WorkloadClusterMismatch)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn windows_follow_time() -> anyhow::Result<()> {
+        let cluster: Cluster = serde_json::from_str(TINY_CLUSTER)?;
+        let (a, b) = (("a", "r0", "p0"), ("b", "r2", "p1"));
+        let entries = [(3, entry(a, b)), (7, entry(a, b)), (25, entry(b, a))]
+            .into_iter()
+            .map(|(timestamp, entry)| Entry { timestamp, ..entry });
+        let opts = SpatialOpts::builder().window(10).build();
+        let spatial = SpatialData::from_entries(entries, &opts)?;
+        let windows = spatial.windows.as_ref().unwrap();
+        assert_eq!(
+            windows.windows.iter().map(|w| w.start).collect::<Vec<_>>(),
+            vec![0, 20]
+        );
+        assert_eq!(
+            spatial.matrix.entries().map(|(_, _, c)| c).sum::<usize>(),
+            3
+        );
+
+        // Hosts 0-3 are in the first pod
+        let mut rng = StdRng::seed_from_u64(0);
+        let workload = spatial.map_to(&cluster, &mut rng)?;
+        assert_eq!(workload.nr_windows(), 2);
+        for (slot, first_pod) in [(0, true), (1, true), (2, false), (5, false)] {
+            let (src, _) = workload.sample_at(slot, &mut rng);
+            assert_eq!(src.inner() < 4, first_pod, "slot {slot}");
+        }
+
+        let opts = SpatialOpts::builder().window(0).build();
+        let result = SpatialData::from_entries([entry(a, b)], &opts);
+        assert!(matches!(result, Err(Error::ZeroWindowWidth)));
+        let mut zero_width = spatial;
+        zero_width.windows.as_mut().unwrap().width = 0;
+        let result = zero_width.map_to(&cluster, &mut rng);
+        assert!(matches!(result, Err(Error::ZeroWindowWidth)));
+        Ok(())
+    }
+
+    #[test]
+    fn weightings_differ() -> anyhow::Result<()> {
+        let (a, b, c) = (("a", "r0", "p0"), ("b", "r1", "p0"), ("c", "r2", "p1"));
+        let packet = |src, dst, srcport: &str, packetlength| Entry {
+            packetlength: Some(packetlength),
+            srcport: Some(srcport.to_string()),
+            ..entry(src, dst)
+        };
+        let entries = || {
+            [
+                packet(a, b, "1", 1000),
+                packet(a, b, "1", 1000),
+                packet(a, b, "2", 1000),
+                packet(a, c, "1", 10),
+            ]
+        };
+        let cell = |spatial: &SpatialData, src: &str, dst: &str| {
+            let idx = |name| spatial.matrix.idx2name.iter().position(|n| n == name);
+            spatial.matrix.get(idx(src).unwrap(), idx(dst).unwrap())
+        };
+        for (weighting, ab, ac) in [
+            (Weighting::Count, 3, 1),
+            (Weighting::Bytes, 3000, 10),
+            (Weighting::Flows, 2, 1),
+        ] {
+            let opts = SpatialOpts::builder().weighting(weighting).build();
+            let spatial = SpatialData::from_entries(entries(), &opts)?;
+            assert_eq!(spatial.weighting, weighting);
+            assert_eq!(cell(&spatial, "r0", "r1"), ab, "{weighting:?}");
+            assert_eq!(cell(&spatial, "r0", "r2"), ac, "{weighting:?}");
+        }
+
+        // Byte weighting needs packet lengths
+        let opts = SpatialOpts::builder().weighting(Weighting::Bytes).build();
+        let result = SpatialData::from_entries([entry(a, b)], &opts);
+        assert!(matches!(result, Err(Error::MissingField(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_bounds_rejected() {
+        let sparse = serde_json::json!({ "dim": 2, "entries": [[0, 2, 1]], "idx2name": names(2) });
+        assert!(serde_json::from_value::<Tor2TorMatrix>(sparse).is_err());
+    }
+}
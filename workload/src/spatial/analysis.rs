@@ -0,0 +1,349 @@
+use ordered_float::OrderedFloat;
+use rustc_hash::FxHashMap;
+
+use super::{Locality, SpatialData, Tor2TorMatrix};
+
+/// Summary statistics of a traffic matrix.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MatrixReport {
+    pub nr_pods: usize,
+    pub nr_racks: usize,
+    /// The number of nonzero rack pairs.
+    pub nnz: usize,
+    pub total: usize,
+    pub locality: LocalityFractions,
+    /// Entropy of the distribution over rack pairs, in bits.
+    pub entropy: f64,
+    /// `entropy` divided by its maximum, `log2(nr_racks^2)`.
+    pub normalized_entropy: f64,
+    /// Gini coefficient of per-rack volumes (traffic sent plus received).
+    pub rack_volume_gini: f64,
+    /// Skewness of per-rack volumes.
+    pub rack_volume_skewness: f64,
+    /// `1 - sum(|A[i][j] - A[j][i]|) / (2 * sum(A))`; 1 for a perfectly symmetric matrix.
+    pub symmetry: f64,
+    /// The heaviest rack pairs, in decreasing order of count.
+    pub top_pairs: Vec<HeavyPair>,
+}
+
+impl MatrixReport {
+    pub fn new(spatial: &SpatialData, top_k: usize) -> Self {
+        let matrix = &spatial.matrix;
+        let total = matrix.total();
+        let nr_racks = matrix.idx2name.len();
+
+        let entropy = -matrix
+            .entries()
+            .map(|(_, _, count)| {
+                let p = count as f64 / total as f64;
+                p * p.log2()
+            })
+            .sum::<f64>();
+        let max_entropy = 2.0 * (nr_racks as f64).log2();
+
+        let volumes = rack_volumes(matrix);
+        let asymmetry = matrix
+            .entries()
+            .map(|(src, dst, count)| match matrix.get(dst, src) {
+                // The transposed cell is not visited, so account for it here
+                0 => 2 * count,
+                other => count.abs_diff(other),
+            })
+            .sum::<usize>();
+
+        let mut top_pairs = matrix.entries().collect::<Vec<_>>();
+        top_pairs.sort_by(|a, b| b.2.cmp(&a.2).then((a.0, a.1).cmp(&(b.0, b.1))));
+        let top_pairs = top_pairs
+            .into_iter()
+            .take(top_k)
+            .map(|(src, dst, count)| HeavyPair {
+                src: matrix.idx2name[src].clone(),
+                dst: matrix.idx2name[dst].clone(),
+                count,
+                frac: count as f64 / total as f64,
+            })
+            .collect();
+
+        Self {
+            nr_pods: spatial.pod2tors.len(),
+            nr_racks,
+            nnz: matrix.nnz(),
+            total,
+            locality: LocalityFractions::new(spatial),
+            entropy,
+            normalized_entropy: if max_entropy > 0.0 {
+                entropy / max_entropy
+            } else {
+                0.0
+            },
+            rack_volume_gini: gini(&volumes),
+            rack_volume_skewness: skewness(&volumes),
+            symmetry: 1.0 - asymmetry as f64 / (2 * total) as f64,
+            top_pairs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HeavyPair {
+    pub src: String,
+    pub dst: String,
+    pub count: usize,
+    pub frac: f64,
+}
+
+/// The fraction of traffic in each locality class.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct LocalityFractions {
+    pub intra_rack: f64,
+    pub intra_pod: f64,
+    pub inter_pod: f64,
+}
+
+impl LocalityFractions {
+    pub fn new(spatial: &SpatialData) -> Self {
+        let total = spatial.matrix.total() as f64;
+        let mut fracs = Self::default();
+        for (_, _, count, locality) in spatial.localized_entries() {
+            let frac = count as f64 / total;
+            match locality {
+                Locality::IntraRack => fracs.intra_rack += frac,
+                Locality::IntraPod => fracs.intra_pod += frac,
+                Locality::InterPod => fracs.inter_pod += frac,
+            }
+        }
+        fracs
+    }
+
+    /// Traffic that stays within a pod, including intra-rack traffic.
+    pub fn within_pod(&self) -> f64 {
+        self.intra_rack + self.intra_pod
+    }
+}
+
+/// How far apart two traffic matrices are.
+///
+/// Matrices over different racks (e.g. two clusters) are compared through scale-free
+/// statistics. Cell-level distance is only defined over the racks the two have in common, as
+/// with a downsampled matrix and its original.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Comparison {
+    pub shared_racks: usize,
+    /// Total variation distance between the two matrices restricted to their shared racks, each
+    /// renormalized. `None` if they share no traffic.
+    pub shared_cell_distance: Option<f64>,
+    /// Kolmogorov-Smirnov distance between the distributions of per-rack row weights, each
+    /// scaled by the number of racks.
+    pub row_weight_ks: f64,
+    /// As `row_weight_ks`, for column weights.
+    pub col_weight_ks: f64,
+    /// `b - a` for the fraction of intra-rack traffic.
+    pub diag_weight_diff: f64,
+    /// `b - a` for the fraction of traffic that stays within a pod.
+    pub within_pod_diff: f64,
+    /// `b - a` for normalized entropy.
+    pub normalized_entropy_diff: f64,
+}
+
+impl Comparison {
+    pub fn new(a: &SpatialData, b: &SpatialData) -> Self {
+        let (report_a, report_b) = (MatrixReport::new(a, 0), MatrixReport::new(b, 0));
+        let scaled = |matrix: &Tor2TorMatrix, weights: Vec<f64>| {
+            let n = matrix.idx2name.len() as f64;
+            weights
+                .into_iter()
+                .take(matrix.idx2name.len())
+                .map(|w| w * n)
+                .collect::<Vec<_>>()
+        };
+        let row_weight_ks = ks_distance(
+            &scaled(&a.matrix, a.matrix.row_weights().collect()),
+            &scaled(&b.matrix, b.matrix.row_weights().collect()),
+        );
+        let col_weight_ks = ks_distance(
+            &scaled(&a.matrix, a.matrix.col_weights().collect()),
+            &scaled(&b.matrix, b.matrix.col_weights().collect()),
+        );
+        let (shared_racks, shared_cell_distance) = shared_cell_distance(&a.matrix, &b.matrix);
+        Self {
+            shared_racks,
+            shared_cell_distance,
+            row_weight_ks,
+            col_weight_ks,
+            diag_weight_diff: b.matrix.diag_weight() - a.matrix.diag_weight(),
+            within_pod_diff: report_b.locality.within_pod() - report_a.locality.within_pod(),
+            normalized_entropy_diff: report_b.normalized_entropy - report_a.normalized_entropy,
+        }
+    }
+}
+
+fn shared_cell_distance(a: &Tor2TorMatrix, b: &Tor2TorMatrix) -> (usize, Option<f64>) {
+    let b_name2idx = b
+        .idx2name
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name, i))
+        .collect::<FxHashMap<_, _>>();
+    // Maps an index in `a` to the index of the same rack in `b`
+    let a2b = a
+        .idx2name
+        .iter()
+        .enumerate()
+        .filter_map(|(i, name)| Some((i, *b_name2idx.get(name)?)))
+        .collect::<FxHashMap<_, _>>();
+    let b2a = a2b
+        .iter()
+        .map(|(&i, &j)| (j, i))
+        .collect::<FxHashMap<_, _>>();
+
+    // Both matrices restricted to shared racks, keyed by `a`'s indices
+    let a_cells = a
+        .entries()
+        .filter(|(src, dst, _)| a2b.contains_key(src) && a2b.contains_key(dst))
+        .map(|(src, dst, count)| ((src, dst), count))
+        .collect::<FxHashMap<_, _>>();
+    let b_cells = b
+        .entries()
+        .filter_map(|(src, dst, count)| Some(((*b2a.get(&src)?, *b2a.get(&dst)?), count)))
+        .collect::<FxHashMap<_, _>>();
+    let (a_total, b_total) = (
+        a_cells.values().sum::<usize>() as f64,
+        b_cells.values().sum::<usize>() as f64,
+    );
+    if a_total == 0.0 || b_total == 0.0 {
+        return (a2b.len(), None);
+    }
+    let mut distance = 0.0;
+    for (cell, &count) in &a_cells {
+        let other = b_cells.get(cell).copied().unwrap_or(0);
+        distance += (count as f64 / a_total - other as f64 / b_total).abs();
+    }
+    for (cell, &count) in &b_cells {
+        if !a_cells.contains_key(cell) {
+            distance += count as f64 / b_total;
+        }
+    }
+    (a2b.len(), Some(distance / 2.0))
+}
+
+/// Traffic sent plus received by each named rack.
+pub(crate) fn rack_volumes(matrix: &Tor2TorMatrix) -> Vec<f64> {
+    let mut volumes = vec![0.0; matrix.idx2name.len()];
+    for (src, dst, count) in matrix.entries() {
+        volumes[src] += count as f64;
+        volumes[dst] += count as f64;
+    }
+    volumes
+}
+
+fn gini(values: &[f64]) -> f64 {
+    let sum = values.iter().sum::<f64>();
+    if values.is_empty() || sum == 0.0 {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by_key(|&v| OrderedFloat(v));
+    let n = sorted.len() as f64;
+    let weighted = sorted
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| (i + 1) as f64 * v)
+        .sum::<f64>();
+    2.0 * weighted / (n * sum) - (n + 1.0) / n
+}
+
+fn skewness(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let m2 = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    let m3 = values.iter().map(|v| (v - mean).powi(3)).sum::<f64>() / n;
+    if m2 == 0.0 {
+        0.0
+    } else {
+        m3 / m2.powf(1.5)
+    }
+}
+
+/// The largest vertical distance between the empirical CDFs of two samples.
+fn ks_distance(a: &[f64], b: &[f64]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let mut a = a.iter().map(|&v| OrderedFloat(v)).collect::<Vec<_>>();
+    let mut b = b.iter().map(|&v| OrderedFloat(v)).collect::<Vec<_>>();
+    a.sort();
+    b.sort();
+    let (mut i, mut j, mut max) = (0, 0, 0.0_f64);
+    while i < a.len() && j < b.len() {
+        let x = std::cmp::min(a[i], b[j]);
+        while i < a.len() && a[i] == x {
+            i += 1;
+        }
+        while j < b.len() && b[j] == x {
+            j += 1;
+        }
+        let diff = (i as f64 / a.len() as f64 - j as f64 / b.len() as f64).abs();
+        max = max.max(diff);
+    }
+    max
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spatial::Weighting;
+
+    use super::*;
+
+    fn spatial(entries: &[(usize, usize, usize)]) -> SpatialData {
+        let names = (0..4).map(|i| format!("r{i}")).collect::<Vec<_>>();
+        let pod2tors = [
+            ("p0".to_string(), names[..2].to_vec()),
+            ("p1".to_string(), names[2..].to_vec()),
+        ]
+        .into_iter()
+        .collect();
+        SpatialData {
+            matrix: Tor2TorMatrix::from_entries(4, entries.iter().copied(), names),
+            pod2tors,
+            nr_pods: 2,
+            nr_racks: 4,
+            host_skew: None,
+            windows: None,
+            weighting: Weighting::Count,
+        }
+    }
+
+    #[test]
+    fn report_correct() {
+        let spatial = spatial(&[(0, 0, 2), (0, 1, 3), (1, 0, 3), (1, 2, 2)]);
+        let report = MatrixReport::new(&spatial, 2);
+        assert_eq!(report.total, 10);
+        assert!((report.locality.intra_rack - 0.2).abs() < 1e-9);
+        assert!((report.locality.intra_pod - 0.6).abs() < 1e-9);
+        assert!((report.locality.inter_pod - 0.2).abs() < 1e-9);
+        assert!((report.symmetry - 0.8).abs() < 1e-9);
+        let top = report
+            .top_pairs
+            .iter()
+            .map(|p| (p.src.as_str(), p.dst.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(top, vec![("r0", "r1"), ("r1", "r0")]);
+        // Volumes are [10, 8, 2, 0]
+        assert!((report.rack_volume_gini - 0.45).abs() < 1e-9);
+    }
+
+    #[test]
+    fn comparison_of_identical_matrices_is_zero() {
+        let a = spatial(&[(0, 0, 2), (0, 1, 3), (3, 2, 5)]);
+        let b = spatial(&[(0, 0, 4), (0, 1, 6), (3, 2, 10)]);
+        let cmp = Comparison::new(&a, &b);
+        assert_eq!(cmp.shared_racks, 4);
+        assert_eq!(cmp.shared_cell_distance, Some(0.0));
+        assert_eq!(cmp.row_weight_ks, 0.0);
+        assert_eq!(cmp.diag_weight_diff, 0.0);
+
+        let c = spatial(&[(0, 1, 1)]);
+        let cmp = Comparison::new(&a, &c);
+        assert!((cmp.shared_cell_distance.unwrap() - 0.7).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,317 @@
+use rand::prelude::*;
+use rustc_hash::FxHashMap;
+
+use super::{
+    analysis::{self, Comparison},
+    Error, SpatialData,
+};
+
+/// How [`SpatialData::downsample_with`] chooses which pods and racks to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DownsampleMode {
+    /// Pods and racks are chosen uniformly at random, as in [`SpatialData::downsample`]. Every
+    /// pod must have at least the requested number of racks.
+    Uniform,
+    /// The pods and racks with the most traffic (sent plus received) are kept.
+    TopK,
+    /// Pods and racks are sorted by traffic volume and split into as many equal-sized strata as
+    /// there are items to keep; one item is chosen at random from each stratum.
+    Stratified,
+    /// Starting from a stratified sample, racks and pods are swapped for ones that were left out
+    /// whenever that brings the row and column weight distributions, the intra-rack fraction and
+    /// the within-pod fraction closer to the original's.
+    Optimized { iterations: usize },
+}
+
+impl SpatialData {
+    /// Downsamples to `nr_pods` pods of `nr_tors_per_pod` racks each, returning the result along
+    /// with how far it deviates from the original.
+    pub fn downsample_with(
+        &self,
+        nr_pods: usize,
+        nr_tors_per_pod: usize,
+        mode: DownsampleMode,
+        mut rng: impl Rng,
+    ) -> Result<(Self, Comparison), Error> {
+        if nr_pods == 0 || nr_tors_per_pod == 0 {
+            return Err(Error::EmptyDownsample);
+        }
+        if self.pod2tors.len() < nr_pods
+            || self
+                .pod2tors
+                .values()
+                .filter(|tors| tors.len() >= nr_tors_per_pod)
+                .count()
+                < nr_pods
+        {
+            return Err(Error::TooFewRacks);
+        }
+        let selection = match mode {
+            DownsampleMode::Uniform => {
+                // Any pod may be chosen, so every pod needs enough racks
+                if self
+                    .pod2tors
+                    .values()
+                    .any(|tors| tors.len() < nr_tors_per_pod)
+                {
+                    return Err(Error::TooFewRacks);
+                }
+                let downsampled = self.downsample(nr_pods, nr_tors_per_pod, &mut rng);
+                let comparison = Comparison::new(self, &downsampled);
+                return Ok((downsampled, comparison));
+            }
+            DownsampleMode::TopK => Selection::top_k(self, nr_pods, nr_tors_per_pod),
+            DownsampleMode::Stratified => {
+                Selection::stratified(self, nr_pods, nr_tors_per_pod, &mut rng)
+            }
+            DownsampleMode::Optimized { iterations } => {
+                let start = Selection::stratified(self, nr_pods, nr_tors_per_pod, &mut rng);
+                start.optimize(self, nr_tors_per_pod, iterations, &mut rng)
+            }
+        };
+        let downsampled = self.restrict_to(selection.pod2tors);
+        let comparison = Comparison::new(self, &downsampled);
+        Ok((downsampled, comparison))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Selection {
+    pod2tors: FxHashMap<String, Vec<String>>,
+}
+
+impl Selection {
+    fn top_k(spatial: &SpatialData, nr_pods: usize, nr_tors_per_pod: usize) -> Self {
+        let volumes = Volumes::new(spatial);
+        let pods = volumes.ranked_pods(spatial, nr_tors_per_pod);
+        let pod2tors = pods
+            .into_iter()
+            .rev()
+            .take(nr_pods)
+            .map(|pod| {
+                let tors = volumes.ranked_tors(spatial, &pod);
+                let tors = tors.into_iter().rev().take(nr_tors_per_pod).collect();
+                (pod, tors)
+            })
+            .collect();
+        Self { pod2tors }
+    }
+
+    fn stratified(
+        spatial: &SpatialData,
+        nr_pods: usize,
+        nr_tors_per_pod: usize,
+        mut rng: impl Rng,
+    ) -> Self {
+        let volumes = Volumes::new(spatial);
+        let pods = volumes.ranked_pods(spatial, nr_tors_per_pod);
+        let pod2tors = choose_stratified(&pods, nr_pods, &mut rng)
+            .into_iter()
+            .map(|pod| {
+                let tors = volumes.ranked_tors(spatial, &pod);
+                let tors = choose_stratified(&tors, nr_tors_per_pod, &mut rng);
+                (pod, tors)
+            })
+            .collect();
+        Self { pod2tors }
+    }
+
+    fn optimize(
+        mut self,
+        spatial: &SpatialData,
+        nr_tors_per_pod: usize,
+        iterations: usize,
+        mut rng: impl Rng,
+    ) -> Self {
+        let volumes = Volumes::new(spatial);
+        let mut best = self.objective(spatial);
+        for _ in 0..iterations {
+            let mut candidate = self.clone();
+            // Mostly swap racks within a kept pod; occasionally swap a whole pod
+            let left_out_pods = volumes
+                .ranked_pods(spatial, nr_tors_per_pod)
+                .into_iter()
+                .filter(|pod| !self.pod2tors.contains_key(pod))
+                .collect::<Vec<_>>();
+            if !left_out_pods.is_empty() && rng.gen_bool(0.1) {
+                let mut kept = candidate.pod2tors.keys().cloned().collect::<Vec<_>>();
+                kept.sort();
+                let out = kept.choose(&mut rng).unwrap().clone();
+                let into = left_out_pods.choose(&mut rng).unwrap().clone();
+                candidate.pod2tors.remove(&out);
+                let tors = volumes.ranked_tors(spatial, &into);
+                let tors = choose_stratified(&tors, nr_tors_per_pod, &mut rng);
+                candidate.pod2tors.insert(into, tors);
+            } else {
+                let mut kept = candidate.pod2tors.keys().cloned().collect::<Vec<_>>();
+                kept.sort();
+                let pod = kept.choose(&mut rng).unwrap();
+                let tors = candidate.pod2tors.get_mut(pod).unwrap();
+                let left_out = spatial.pod2tors[pod]
+                    .iter()
+                    .filter(|tor| !tors.contains(tor))
+                    .collect::<Vec<_>>();
+                let Some(&into) = left_out.choose(&mut rng) else {
+                    continue;
+                };
+                let i = rng.gen_range(0..tors.len());
+                tors[i] = into.clone();
+            }
+            let objective = candidate.objective(spatial);
+            if objective < best {
+                best = objective;
+                self = candidate;
+            }
+        }
+        self
+    }
+
+    fn objective(&self, spatial: &SpatialData) -> f64 {
+        let cmp = Comparison::new(spatial, &spatial.restrict_to(self.pod2tors.clone()));
+        cmp.row_weight_ks
+            + cmp.col_weight_ks
+            + cmp.diag_weight_diff.abs()
+            + cmp.within_pod_diff.abs()
+    }
+}
+
+/// Traffic sent plus received by each rack.
+struct Volumes {
+    tor2volume: FxHashMap<String, f64>,
+}
+
+impl Volumes {
+    fn new(spatial: &SpatialData) -> Self {
+        let tor2volume = spatial
+            .matrix
+            .idx2name
+            .iter()
+            .cloned()
+            .zip(analysis::rack_volumes(&spatial.matrix))
+            .collect();
+        Self { tor2volume }
+    }
+
+    fn tor(&self, tor: &str) -> f64 {
+        self.tor2volume.get(tor).copied().unwrap_or(0.0)
+    }
+
+    /// Pods with at least `nr_tors` racks, in increasing order of volume. Ties are broken by
+    /// name so that results don't depend on hash map order.
+    fn ranked_pods(&self, spatial: &SpatialData, nr_tors: usize) -> Vec<String> {
+        let mut pods = spatial
+            .pod2tors
+            .iter()
+            .filter(|(_, tors)| tors.len() >= nr_tors)
+            .map(|(pod, tors)| (tors.iter().map(|tor| self.tor(tor)).sum::<f64>(), pod))
+            .collect::<Vec<_>>();
+        pods.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(b.1)));
+        pods.into_iter().map(|(_, pod)| pod.clone()).collect()
+    }
+
+    /// The racks of `pod` in increasing order of volume.
+    fn ranked_tors(&self, spatial: &SpatialData, pod: &str) -> Vec<String> {
+        let mut tors = spatial.pod2tors[pod].clone();
+        tors.sort_by(|a, b| self.tor(a).total_cmp(&self.tor(b)).then(a.cmp(b)));
+        tors
+    }
+}
+
+/// Splits `ranked` into `k` contiguous, near-equal strata and picks one item from each.
+fn choose_stratified(ranked: &[String], k: usize, mut rng: impl Rng) -> Vec<String> {
+    let n = ranked.len();
+    (0..k)
+        .map(|i| {
+            let stratum = &ranked[i * n / k..(i + 1) * n / k];
+            stratum.choose(&mut rng).unwrap().clone()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spatial::{Tor2TorMatrix, Weighting};
+
+    use super::*;
+
+    // Two pods of four racks; `r0` and `r4` carry most of the traffic
+    fn spatial() -> SpatialData {
+        let names = (0..8).map(|i| format!("r{i}")).collect::<Vec<_>>();
+        let pod2tors = [
+            ("p0".to_string(), names[..4].to_vec()),
+            ("p1".to_string(), names[4..].to_vec()),
+        ]
+        .into_iter()
+        .collect();
+        let entries = (0..8)
+            .flat_map(|src| (0..8).map(move |dst| (src, dst)))
+            .map(|(src, dst)| {
+                let heavy = [0, 4].contains(&src) || [0, 4].contains(&dst);
+                (src, dst, if heavy { 100 } else { 1 + (src + dst) % 3 })
+            });
+        SpatialData {
+            matrix: Tor2TorMatrix::from_entries(8, entries, names),
+            pod2tors,
+            nr_pods: 2,
+            nr_racks: 8,
+            host_skew: None,
+            windows: None,
+            weighting: Weighting::Count,
+        }
+    }
+
+    #[test]
+    fn top_k_keeps_heavy_racks() -> anyhow::Result<()> {
+        let spatial = spatial();
+        let rng = StdRng::seed_from_u64(0);
+        let (downsampled, _) = spatial.downsample_with(2, 1, DownsampleMode::TopK, rng)?;
+        let mut kept = downsampled.matrix.idx2name.clone();
+        kept.sort();
+        assert_eq!(kept, vec!["r0", "r4"]);
+        Ok(())
+    }
+
+    #[test]
+    fn optimized_no_worse_than_stratified() -> anyhow::Result<()> {
+        let spatial = spatial();
+        for seed in 0..4 {
+            let rng = StdRng::seed_from_u64(seed);
+            let (stratified, _) = spatial.downsample_with(2, 2, DownsampleMode::Stratified, rng)?;
+            let rng = StdRng::seed_from_u64(seed);
+            let mode = DownsampleMode::Optimized { iterations: 50 };
+            let (optimized, _) = spatial.downsample_with(2, 2, mode, rng)?;
+            assert_eq!(optimized.nr_racks, 4);
+            let objective = |d: &SpatialData| {
+                let selection = Selection {
+                    pod2tors: d.pod2tors.clone(),
+                };
+                selection.objective(&spatial)
+            };
+            assert!(objective(&optimized) <= objective(&stratified));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn too_few_racks_rejected() {
+        let rng = StdRng::seed_from_u64(0);
+        let result = spatial().downsample_with(2, 5, DownsampleMode::TopK, rng);
+        assert!(matches!(result, Err(Error::TooFewRacks)));
+
+        // Empty selections would leave the optimizer nothing to swap
+        let iterations = 10;
+        for (nr_pods, nr_tors_per_pod) in [(0, 2), (1, 0)] {
+            let rng = StdRng::seed_from_u64(0);
+            let mode = DownsampleMode::Optimized { iterations };
+            let result = spatial().downsample_with(nr_pods, nr_tors_per_pod, mode, rng);
+            assert!(matches!(result, Err(Error::EmptyDownsample)));
+        }
+
+        // A uniform sample could pick the pod that is one rack short
+        let mut spatial = spatial();
+        spatial.pod2tors.get_mut("p1").unwrap().pop();
+        let rng = StdRng::seed_from_u64(0);
+        let result = spatial.downsample_with(1, 4, DownsampleMode::Uniform, rng);
+        assert!(matches!(result, Err(Error::TooFewRacks)));
+    }
+}